@@ -16,7 +16,7 @@ fn main() {
                 }),
                 ..default()
             }),
-            GamePlugin,
+            GamePlugin::default(),
         ))
         .run();
 }
@@ -1,184 +1,674 @@
+use avian2d::prelude::ExternalImpulse;
+use bevy::input::InputSystem;
 use bevy::math::vec2;
 use bevy::prelude::*;
-use std::collections::VecDeque;
+use bevy::utils::HashSet;
+use bevy::window::PrimaryWindow;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 
-use crate::actions::{Actions, Effect, Step};
-use crate::loading::AudioAssets;
-use crate::player::{Player, PlayerForm};
-use crate::GameState;
+use crate::actions::{Actions, Effect, Spawn, StatusEffect, StatusEffects, StatusKind, Step};
+use crate::audio::Sound;
+use crate::player::{Aim, DashCooldown, Player, PlayerForm};
+use crate::progression::Upgrades;
+use crate::settings::{AimMode, Settings};
+use crate::storage::Storage;
+use crate::PauseState;
 
 pub const FOLLOW_EPSILON: f32 = 5.;
 
+/// After attacking with the sword, before [`Upgrades::cooldown_factor`]
+const SWORD_COOLDOWN_SECS: f32 = 1.0;
+/// After shooting, before [`Upgrades::cooldown_factor`]
+const BOW_COOLDOWN_SECS: f32 = 0.8;
+
+/// Bump this when the meaning of stored bindings changes and migrate in [`InputBindings::migrate`]
+pub const BINDINGS_VERSION: u32 = 2;
+
 #[derive(SystemSet, Clone, Copy, Eq, PartialEq, Debug, Hash)]
 pub struct InputSet;
 
+/// Turns the devices into [`ActionState`]s
+#[derive(SystemSet, Clone, Copy, Eq, PartialEq, Debug, Hash)]
+pub struct ActionStateSet;
+
+/// Systems writing the [`VirtualInput`], they run before the [`ActionState`] is updated
+#[derive(SystemSet, Clone, Copy, Eq, PartialEq, Debug, Hash)]
+pub struct VirtualInputSet;
+
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            keyboard_input
-                .in_set(InputSet)
-                .run_if(in_state(GameState::Playing)),
+        let bindings = InputBindings::load(app.world().resource::<Storage>());
+        app.insert_resource(bindings)
+            .init_resource::<ActionState>()
+            .init_resource::<VirtualInput>()
+            .add_systems(
+                PreUpdate,
+                update_action_state
+                    .in_set(ActionStateSet)
+                    .after(InputSystem)
+                    .after(VirtualInputSet),
+            )
+            .add_systems(
+                FixedUpdate,
+                player_input
+                    .in_set(InputSet)
+                    .run_if(in_state(PauseState::Running)),
+            );
+    }
+}
+
+/// The logical inputs of the game. Devices are mapped to these by [`InputBindings`].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum GameControl {
+    Up,
+    Down,
+    Left,
+    Right,
+    Attack,
+    SwitchForm,
+    Dash,
+    Pause,
+    /// Joins the game as another player, only on gamepads
+    Join,
+}
+
+impl GameControl {
+    pub const ALL: [GameControl; 9] = [
+        GameControl::Up,
+        GameControl::Down,
+        GameControl::Left,
+        GameControl::Right,
+        GameControl::Attack,
+        GameControl::SwitchForm,
+        GameControl::Dash,
+        GameControl::Pause,
+        GameControl::Join,
+    ];
+}
+
+/// How touches are turned into controls
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TouchMode {
+    /// On-screen stick and buttons
+    #[default]
+    VirtualControls,
+    /// Move toward the first pressed touch
+    FollowFinger,
+    Disabled,
+}
+
+/// Maps devices to [`GameControl`]s. Loaded from the stored bindings file, falls back to the
+/// defaults.
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct InputBindings {
+    pub version: u32,
+    pub keyboard: BTreeMap<GameControl, Vec<KeyCode>>,
+    pub gamepad: BTreeMap<GameControl, Vec<GamepadButton>>,
+    pub touch: TouchMode,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use GameControl::*;
+        Self {
+            version: BINDINGS_VERSION,
+            keyboard: BTreeMap::from([
+                (Up, vec![KeyCode::KeyW, KeyCode::ArrowUp]),
+                (Down, vec![KeyCode::KeyS, KeyCode::ArrowDown]),
+                (Left, vec![KeyCode::KeyA, KeyCode::ArrowLeft]),
+                (Right, vec![KeyCode::KeyD, KeyCode::ArrowRight]),
+                (Attack, vec![KeyCode::Space]),
+                (SwitchForm, vec![KeyCode::KeyQ]),
+                (Dash, vec![KeyCode::ShiftLeft]),
+                (Pause, vec![KeyCode::Escape]),
+                (Join, vec![]),
+            ]),
+            gamepad: BTreeMap::from([
+                (Up, vec![GamepadButton::DPadUp]),
+                (Down, vec![GamepadButton::DPadDown]),
+                (Left, vec![GamepadButton::DPadLeft]),
+                (Right, vec![GamepadButton::DPadRight]),
+                (Attack, vec![GamepadButton::South]),
+                (SwitchForm, vec![GamepadButton::North]),
+                (Dash, vec![GamepadButton::East]),
+                (Pause, vec![GamepadButton::Start]),
+                (Join, vec![GamepadButton::Select]),
+            ]),
+            touch: default(),
+        }
+    }
+}
+
+impl InputBindings {
+    fn load(storage: &Storage) -> Self {
+        let Some(bindings) = storage.load::<InputBindings>("bindings") else {
+            return default();
+        };
+        if bindings.version > BINDINGS_VERSION {
+            warn!(
+                "Bindings were saved by a newer version ({}), using defaults",
+                bindings.version
+            );
+            return default();
+        }
+        bindings.migrate()
+    }
+
+    /// Upgrades bindings stored by older versions. Controls added since then get their default
+    /// bindings.
+    fn migrate(mut self) -> Self {
+        let defaults = Self::default();
+        for control in GameControl::ALL {
+            self.keyboard
+                .entry(control)
+                .or_insert_with(|| defaults.keyboard[&control].clone());
+            self.gamepad
+                .entry(control)
+                .or_insert_with(|| defaults.gamepad[&control].clone());
+        }
+        // Version 1 only knew following the finger, which was the default
+        if self.version < 2 && self.touch == TouchMode::FollowFinger {
+            self.touch = TouchMode::VirtualControls;
+        }
+        self.version = BINDINGS_VERSION;
+        self
+    }
+
+    pub fn save(&self, storage: &Storage) {
+        storage.save("bindings", self);
+    }
+
+    pub fn keys(&self, control: GameControl) -> &[KeyCode] {
+        self.keyboard
+            .get(&control)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn buttons(&self, control: GameControl) -> &[GamepadButton] {
+        self.gamepad
+            .get(&control)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Binds the key in place of the one in `slot`, the other keys of the control stay
+    pub fn bind_key(&mut self, control: GameControl, slot: usize, key: KeyCode) {
+        bind(self.keyboard.entry(control).or_default(), slot, key);
+    }
+
+    pub fn bind_button(&mut self, control: GameControl, slot: usize, button: GamepadButton) {
+        bind(self.gamepad.entry(control).or_default(), slot, button);
+    }
+
+    /// Other controls sharing a key or button with `control`
+    pub fn conflicts(&self, control: GameControl) -> Vec<GameControl> {
+        GameControl::ALL
+            .into_iter()
+            .filter(|other| *other != control)
+            .filter(|other| {
+                self.keys(*other)
+                    .iter()
+                    .any(|key| self.keys(control).contains(key))
+                    || self
+                        .buttons(*other)
+                        .iter()
+                        .any(|button| self.buttons(control).contains(button))
+            })
+            .collect()
+    }
+
+    fn pressed<'a>(
+        &self,
+        control: GameControl,
+        keyboard_input: Option<&ButtonInput<KeyCode>>,
+        gamepads: impl IntoIterator<Item = &'a Gamepad>,
+    ) -> bool {
+        keyboard_input.is_some_and(|k| k.any_pressed(self.keys(control).iter().copied()))
+            || gamepads
+                .into_iter()
+                .any(|g| g.any_pressed(self.buttons(control).iter().copied()))
+    }
+}
+
+/// The devices a player is controlled with
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlayerDevices {
+    /// Keyboard, mouse, touch and every gamepad no other player joined with
+    Shared,
+    /// Only this gamepad
+    Gamepad(Entity),
+    /// None, the [`ActionState`] is played back from a replay
+    Replay,
+}
+
+/// State of all [`GameControl`]s in the current frame.
+/// As a resource it combines all devices, menus read that one. Every player has their own
+/// component with just their [`PlayerDevices`]. Gameplay reads these instead of the raw input.
+#[derive(Resource, Component, Default)]
+pub struct ActionState {
+    /// Desired movement, not normalized
+    pub movement: Vec2,
+    /// World position to move toward, i.e. a touch
+    pub move_target: Option<Vec2>,
+    /// Direction to aim at, i.e. from the right stick
+    pub aim_direction: Option<Vec2>,
+    /// World position to aim at, i.e. the mouse cursor
+    pub aim_target: Option<Vec2>,
+    pressed: HashSet<GameControl>,
+    just_pressed: HashSet<GameControl>,
+    /// Pressed since the last gameplay tick consumed them
+    triggered: HashSet<GameControl>,
+    /// `triggered` right after the last update, before any tick consumed it
+    frame_triggered: HashSet<GameControl>,
+}
+
+/// Puts the binding into the slot, after the others if that one is empty. If it's bound in
+/// another slot already, the two swap.
+fn bind<T: PartialEq>(bound: &mut Vec<T>, slot: usize, binding: T) {
+    match bound.iter().position(|other| *other == binding) {
+        Some(other) if slot < bound.len() => bound.swap(other, slot),
+        Some(_) => {}
+        None if slot < bound.len() => bound[slot] = binding,
+        None => bound.push(binding),
+    }
+}
+
+/// Controls pressed on screen, merged into the [`ActionState`] with
+/// [`TouchMode::VirtualControls`]
+#[derive(Resource, Default)]
+pub struct VirtualInput {
+    /// Stick deflection, at most 1 long
+    pub movement: Vec2,
+    pub pressed: HashSet<GameControl>,
+}
+
+impl ActionState {
+    pub fn pressed(&self, control: GameControl) -> bool {
+        self.pressed.contains(&control)
+    }
+
+    pub fn just_pressed(&self, control: GameControl) -> bool {
+        self.just_pressed.contains(&control)
+    }
+
+    /// Pressed since the last gameplay tick. Gameplay runs in `FixedUpdate`, which runs several
+    /// times or not at all in a frame, so it reads this instead of [`Self::just_pressed`].
+    pub fn triggered(&self, control: GameControl) -> bool {
+        self.triggered.contains(&control)
+    }
+
+    /// What [`Self::triggered`] was at the start of the frame, before gameplay consumed it
+    pub fn frame_triggered(&self, control: GameControl) -> bool {
+        self.frame_triggered.contains(&control)
+    }
+
+    /// Called at the end of every gameplay tick
+    pub fn consume_triggered(&mut self) {
+        self.triggered.clear();
+    }
+
+    /// Overwrites the controls, i.e. when playing back a replay.
+    /// The just pressed ones are also the triggered ones.
+    pub fn restore(&mut self, pressed: HashSet<GameControl>, just_pressed: HashSet<GameControl>) {
+        self.pressed = pressed;
+        self.triggered.clone_from(&just_pressed);
+        self.frame_triggered.clone_from(&just_pressed);
+        self.just_pressed = just_pressed;
+    }
+
+    fn update(&mut self, input: DeviceInput) {
+        self.just_pressed = input.pressed.difference(&self.pressed).copied().collect();
+        self.triggered.extend(self.just_pressed.iter().copied());
+        self.frame_triggered.clone_from(&self.triggered);
+        self.pressed = input.pressed;
+        self.movement = input.movement;
+        self.aim_direction = input.aim_direction;
+        self.move_target = None;
+        self.aim_target = None;
+    }
+}
+
+/// What a set of devices reports in the current frame
+#[derive(Clone, Default)]
+struct DeviceInput {
+    pressed: HashSet<GameControl>,
+    movement: Vec2,
+    aim_direction: Option<Vec2>,
+}
+
+impl DeviceInput {
+    fn read(
+        bindings: &InputBindings,
+        keyboard_input: Option<&ButtonInput<KeyCode>>,
+        gamepads: &[&Gamepad],
+        virtual_input: Option<&VirtualInput>,
+    ) -> Self {
+        let pressed: HashSet<GameControl> = GameControl::ALL
+            .into_iter()
+            .filter(|control| {
+                bindings.pressed(*control, keyboard_input, gamepads.iter().copied())
+                    || virtual_input.is_some_and(|v| v.pressed.contains(control))
+            })
+            .collect();
+
+        let value = |control| {
+            if pressed.contains(&control) {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        let mut movement = vec2(
+            value(GameControl::Right) - value(GameControl::Left),
+            value(GameControl::Up) - value(GameControl::Down),
         );
+        let left_stick = gamepads
+            .iter()
+            .flat_map(|g| {
+                let dir = g.left_stick();
+                const DEAD_ZONE: f32 = 0.1;
+                (dir.length() > DEAD_ZONE).then_some(dir)
+            })
+            .next();
+        if let Some(left_stick) = left_stick {
+            movement = left_stick;
+        }
+        if let Some(virtual_input) = virtual_input.filter(|v| v.movement != Vec2::ZERO) {
+            movement = virtual_input.movement;
+        }
+
+        let aim_direction = gamepads
+            .iter()
+            .flat_map(|g| {
+                let dir = g.right_stick();
+                const DEAD_ZONE: f32 = 0.2;
+                (dir.length() > DEAD_ZONE).then_some(dir)
+            })
+            .next();
+
+        Self {
+            pressed,
+            movement,
+            aim_direction,
+        }
     }
 }
 
-fn keyboard_input(
+#[allow(clippy::too_many_arguments)]
+fn update_action_state(
+    bindings: Res<InputBindings>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    // Mush all gamepads together...
-    gamepad_input: Query<&Gamepad>,
+    gamepad_query: Query<(Entity, &Gamepad)>,
     touch_input: Res<Touches>,
-    mut player: Query<(&mut Actions, &Transform, &Player)>,
+    virtual_input: Res<VirtualInput>,
+    mut cursor_moved_events: EventReader<CursorMoved>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
     camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
-    audio_assets: Res<AudioAssets>,
-    time: Res<Time>,
+    mut action_state: ResMut<ActionState>,
+    mut player_query: Query<(&PlayerDevices, &mut ActionState)>,
+    mut aiming_with_mouse: Local<bool>,
+    mut last_movement: Local<Vec2>,
 ) {
-    let Ok((mut actions, player_transform, player)) = player.get_single_mut() else {
-        return;
-    };
-
-    let mut player_direction = Vec2::new(
-        get_movement(GameControl::Right, &keyboard_input)
-            - get_movement(GameControl::Left, &keyboard_input),
-        get_movement(GameControl::Up, &keyboard_input)
-            - get_movement(GameControl::Down, &keyboard_input),
-    );
-
-    let left_stick = gamepad_input
+    let virtual_input = (bindings.touch == TouchMode::VirtualControls).then_some(&*virtual_input);
+    let claimed: HashSet<Entity> = player_query
         .iter()
-        .flat_map(|g| {
-            let (Some(x), Some(y)) = (
-                g.get(GamepadAxis::LeftStickX),
-                g.get(GamepadAxis::LeftStickY),
-            ) else {
-                return None;
-            };
-            let dir = vec2(x, y);
-            const DEAD_ZONE: f32 = 0.1;
-            (dir.length() > DEAD_ZONE).then_some(dir)
+        .filter_map(|(devices, _)| match devices {
+            PlayerDevices::Gamepad(gamepad) => Some(*gamepad),
+            PlayerDevices::Shared | PlayerDevices::Replay => None,
         })
-        .next();
-    if let Some(left_stick) = left_stick {
-        player_direction = left_stick;
+        .collect();
+    let all_gamepads: Vec<&Gamepad> = gamepad_query.iter().map(|(_, g)| g).collect();
+    let shared_gamepads: Vec<&Gamepad> = gamepad_query
+        .iter()
+        .filter(|(entity, _)| !claimed.contains(entity))
+        .map(|(_, g)| g)
+        .collect();
+
+    let mut move_target = None;
+    if bindings.touch == TouchMode::FollowFinger {
+        if let (Some(touch_position), Ok((camera, camera_transform))) =
+            (touch_input.first_pressed_position(), camera.get_single())
+        {
+            move_target = camera
+                .viewport_to_world_2d(camera_transform, touch_position)
+                .ok();
+        }
+    }
+
+    let shared = DeviceInput::read(
+        &bindings,
+        Some(&keyboard_input),
+        &shared_gamepads,
+        virtual_input,
+    );
+    // Whichever was used last wins: The right stick or the mouse. Starting to move with the keys
+    // or the left stick, while the mouse rests, aims where the character moves again.
+    let cursor_moved = cursor_moved_events.read().count() > 0;
+    let started_moving = shared.movement != Vec2::ZERO && *last_movement == Vec2::ZERO;
+    *last_movement = shared.movement;
+    if shared.aim_direction.is_some() || (started_moving && !cursor_moved) {
+        *aiming_with_mouse = false;
+    } else if cursor_moved {
+        *aiming_with_mouse = true;
+    }
+    let mut aim_target = None;
+    if *aiming_with_mouse {
+        if let (Some(cursor_position), Ok((camera, camera_transform))) = (
+            window_query
+                .get_single()
+                .ok()
+                .and_then(Window::cursor_position),
+            camera.get_single(),
+        ) {
+            aim_target = camera
+                .viewport_to_world_2d(camera_transform, cursor_position)
+                .ok();
+        }
     }
 
-    if let Some(touch_position) = touch_input.first_pressed_position() {
-        let (camera, camera_transform) = camera.single();
-        if let Ok(touch_position) = camera.viewport_to_world_2d(camera_transform, touch_position) {
-            let diff = touch_position - player_transform.translation.xy();
+    for (devices, mut player_action_state) in player_query.iter_mut() {
+        match devices {
+            PlayerDevices::Shared => {
+                player_action_state.update(shared.clone());
+                player_action_state.move_target = move_target;
+                player_action_state.aim_target = aim_target;
+            }
+            PlayerDevices::Gamepad(gamepad) => {
+                // A disconnected gamepad leaves its player standing still
+                let gamepads: Vec<&Gamepad> = gamepad_query
+                    .get(*gamepad)
+                    .map(|(_, g)| g)
+                    .into_iter()
+                    .collect();
+                player_action_state.update(DeviceInput::read(&bindings, None, &gamepads, None));
+            }
+            PlayerDevices::Replay => (),
+        }
+    }
+
+    action_state.update(DeviceInput::read(
+        &bindings,
+        Some(&keyboard_input),
+        &all_gamepads,
+        virtual_input,
+    ));
+    action_state.move_target = move_target;
+    action_state.aim_target = aim_target;
+}
+
+fn player_input(
+    settings: Res<Settings>,
+    mut player_query: Query<(
+        Entity,
+        &mut ActionState,
+        &mut Actions,
+        &Transform,
+        &Player,
+        &mut DashCooldown,
+        &mut Aim,
+        &StatusEffects,
+        &Upgrades,
+    )>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (
+        player_entity,
+        mut action_state,
+        mut actions,
+        player_transform,
+        player,
+        mut dash_cooldown,
+        mut aim,
+        status_effects,
+        upgrades,
+    ) in player_query.iter_mut()
+    {
+        if status_effects.stunned() {
+            action_state.consume_triggered();
+            continue;
+        }
+        let actions = &mut *actions;
+
+        let mut player_direction = action_state.movement;
+
+        if let Some(move_target) = action_state.move_target {
+            let diff = move_target - player_transform.translation.xy();
             if diff.length() > FOLLOW_EPSILON {
                 player_direction = diff.normalize();
             }
         }
-    }
 
-    let player_direction = if player_direction != Vec2::ZERO {
-        Some(player_direction.normalize())
-    } else {
-        None
-    };
+        let player_direction = if player_direction != Vec2::ZERO {
+            Some(player_direction.normalize())
+        } else {
+            None
+        };
 
-    let actions = &mut *actions;
-    let triggering = keyboard_input.pressed(KeyCode::Space)
-        || gamepad_input
-            .iter()
-            .any(|g| g.pressed(GamepadButton::South));
-    if !triggering {
-        if let Actions::Charging {
-            charge,
-            trigger_direction,
-        } = actions
+        aim.0 = match settings.aim_mode {
+            AimMode::Independent => action_state
+                .aim_direction
+                .or_else(|| {
+                    action_state
+                        .aim_target
+                        .map(|target| target - player_transform.translation.xy())
+                })
+                .and_then(Vec2::try_normalize),
+            AimMode::Movement => None,
+        };
+        // Attacks go where the player aims, or where they are moving otherwise
+        let attack_direction = aim.0.or(player_direction);
+
+        dash_cooldown.tick(time.delta());
+        if action_state.triggered(GameControl::Dash)
+            && dash_cooldown.finished()
+            && !matches!(actions, Actions::Executing { .. })
         {
-            if let Some(trigger_direction) = player_direction.or(*trigger_direction) {
-                match player.form {
-                    PlayerForm::Sword => {
-                        let mut steps = VecDeque::from([Step::from_timer(Timer::from_seconds(
-                            0.0,
-                            TimerMode::Once,
-                        ))
-                        .with_effect(Effect::Circle)
-                        .with_forward(20.0)
-                        .with_sfx(audio_assets.woosh.clone())]);
-                        for _ in 0..charge.elapsed_secs() as u32 {
-                            steps.push_back(
-                                Step::from_timer(Timer::from_seconds(0.2, TimerMode::Once))
+            if let Some(dash_direction) = player_direction {
+                commands
+                    .entity(player_entity)
+                    .insert(ExternalImpulse::new(dash_direction * 40000.0));
+                dash_cooldown.reset();
+            }
+        }
+
+        let triggering = action_state.pressed(GameControl::Attack);
+        if !triggering {
+            if let Actions::Charging {
+                charge,
+                trigger_direction,
+            } = actions
+            {
+                if let Some(trigger_direction) = attack_direction.or(*trigger_direction) {
+                    match player.form {
+                        PlayerForm::Sword => {
+                            let swing = |secs| {
+                                Step::from_timer(Timer::from_seconds(secs, TimerMode::Once))
                                     .with_effect(Effect::Circle)
                                     .with_forward(20.0)
-                                    .with_sfx(audio_assets.woosh.clone()),
-                            );
+                                    .with_sfx(Sound::Woosh)
+                                    .with_radius(upgrades.slash_radius)
+                            };
+                            let mut steps = VecDeque::from([swing(0.0)]);
+                            // The swings of a charged attack are flaming, the last one of a
+                            // full charge stuns instead
+                            let charged_swings = charge.elapsed_secs() as u32;
+                            for i in 0..charged_swings {
+                                let status = if charge.finished() && i + 1 == charged_swings {
+                                    StatusEffect::new(StatusKind::Stun, 1.0)
+                                } else {
+                                    StatusEffect::new(StatusKind::Burn, 3.0)
+                                };
+                                steps.push_back(swing(0.2).with_status(status).with_shake(0.15));
+                            }
+                            for _ in 0..upgrades.extra_swings {
+                                steps.push_back(swing(0.2));
+                            }
+                            *actions = Actions::Executing {
+                                trigger_direction,
+                                pending_cooldown: Timer::from_seconds(
+                                    SWORD_COOLDOWN_SECS * upgrades.cooldown_factor,
+                                    TimerMode::Once,
+                                ),
+                                steps,
+                            };
+                        }
+                        PlayerForm::Bow => {
+                            // One more arrow per second charged
+                            let steps = (0..=charge.elapsed_secs() as u32)
+                                .map(|i| {
+                                    Step::from_timer(Timer::from_seconds(
+                                        if i == 0 { 0.0 } else { 0.15 },
+                                        TimerMode::Once,
+                                    ))
+                                    .with_effect(Effect::Spawn(Spawn::PlayerArrow))
+                                    .with_sfx(Sound::Woosh)
+                                    .with_radius(0.0)
+                                })
+                                .collect();
+                            *actions = Actions::Executing {
+                                trigger_direction,
+                                pending_cooldown: Timer::from_seconds(
+                                    BOW_COOLDOWN_SECS * upgrades.cooldown_factor,
+                                    TimerMode::Once,
+                                ),
+                                steps,
+                            };
                         }
-                        *actions = Actions::Executing {
-                            trigger_direction,
-                            pending_cooldown: Timer::from_seconds(1.0, TimerMode::Once),
-                            steps,
-                        };
                     }
-                    PlayerForm::Bow => todo!(),
+                } else {
+                    // No direction was selected, nothing will be done but no cool-down will be
+                    // applied
+                    *actions = Actions::Idle;
+                }
+                // Unless charging, stopping releasing the trigger will not do anything
+            } else if let Actions::Idle = actions {
+                if action_state.triggered(GameControl::SwitchForm) {
+                    *actions = Actions::ChangePlayerForm(player.form.next());
                 }
-            } else {
-                // No direction was selected, nothing will be done but no cool-down will be
-                // applied
-                *actions = Actions::Idle;
-            }
-            // Unless charging, stopping releasing the trigger will not do anything
-        } else if let Actions::Idle = actions {
-            if keyboard_input.pressed(KeyCode::Digit1) {
-                *actions = Actions::ChangePlayerForm(PlayerForm::Sword);
-            } else if keyboard_input.pressed(KeyCode::Digit2) {
-                *actions = Actions::ChangePlayerForm(PlayerForm::Bow);
-            }
-        }
-    } else {
-        if matches!(actions, Actions::Idle) {
-            *actions = Actions::Charging {
-                charge: default(),
-                trigger_direction: default(),
-            };
-        }
-        if let Actions::Charging {
-            charge,
-            trigger_direction,
-        } = actions
-        {
-            charge.tick(time.delta());
-            *trigger_direction = player_direction.or(*trigger_direction);
-        }
-        // No other Action state allows charging currently
-    }
-}
-
-pub enum GameControl {
-    Up,
-    Down,
-    Left,
-    Right,
-}
-
-impl GameControl {
-    pub fn pressed(&self, keyboard_input: &Res<ButtonInput<KeyCode>>) -> bool {
-        match self {
-            GameControl::Up => {
-                keyboard_input.pressed(KeyCode::KeyW) || keyboard_input.pressed(KeyCode::ArrowUp)
-            }
-            GameControl::Down => {
-                keyboard_input.pressed(KeyCode::KeyS) || keyboard_input.pressed(KeyCode::ArrowDown)
             }
-            GameControl::Left => {
-                keyboard_input.pressed(KeyCode::KeyA) || keyboard_input.pressed(KeyCode::ArrowLeft)
+        } else {
+            if matches!(actions, Actions::Idle) {
+                *actions = Actions::Charging {
+                    charge: default(),
+                    trigger_direction: default(),
+                };
             }
-            GameControl::Right => {
-                keyboard_input.pressed(KeyCode::KeyD) || keyboard_input.pressed(KeyCode::ArrowRight)
+            if let Actions::Charging {
+                charge,
+                trigger_direction,
+            } = actions
+            {
+                charge.tick(time.delta());
+                *trigger_direction = attack_direction.or(*trigger_direction);
             }
+            // No other Action state allows charging currently
         }
-    }
-}
-
-pub fn get_movement(control: GameControl, input: &Res<ButtonInput<KeyCode>>) -> f32 {
-    if control.pressed(input) {
-        1.0
-    } else {
-        0.0
+        action_state.consume_triggered();
     }
 }
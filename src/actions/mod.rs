@@ -5,40 +5,75 @@ use bevy_enoki::{
     prelude::{OneShot, ParticleEffectHandle},
     ParticleSpawner,
 };
-use bevy_kira_audio::prelude::*;
+use rand::Rng;
 use std::collections::VecDeque;
 
+use crate::audio::{PlaySfx, Sound};
+use crate::camera::CameraImpulse;
 use crate::enemies::{Ai, EnemyForm};
-use crate::loading::{AudioAssets, EffectAssets, RangedEnemyAssets, TextureAssets};
+use crate::game_over::{RunStats, PLAYER_DEATH_SECS};
+use crate::loading::{EffectAssets, RangedEnemyAssets, TextureAssets};
 use crate::physics::CollisionLayer;
+use crate::pickups::ScoreMultiplier;
 use crate::player::{Player, PlayerForm};
-use crate::GameState;
-use game_control::{InputPlugin, InputSet};
+use crate::seed::RunRng;
+use crate::{GameState, PauseState};
+use game_control::InputPlugin;
+use status_effects::{tick_status_effects, update_status_icons};
+
+pub use game_control::{
+    ActionState, ActionStateSet, GameControl, InputBindings, InputSet, PlayerDevices, TouchMode,
+    VirtualInput, VirtualInputSet,
+};
+pub use status_effects::{StatusEffect, StatusEffects, StatusKind};
 
 mod game_control;
+mod status_effects;
 
 pub struct ActionsPlugin;
 
 // This plugin listens for keyboard input and converts the input into Actions.
 // Actions can then be used as a resource in other systems to act on the player input.
+// All of the gameplay runs in `FixedUpdate`, so it plays the same at any frame rate.
 impl Plugin for ActionsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                character_actions,
-                character_movement,
-                despawn_dead,
-                hit_detection,
+        app.add_event::<DamageEvent>()
+            .add_event::<DeathEvent>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    tick_status_effects,
+                    character_actions,
+                    character_movement,
+                    despawn_dead,
+                    hit_detection,
+                    apply_damage,
+                )
+                    .chain()
+                    .in_set(CombatSet)
+                    .after(InputSet)
+                    .run_if(in_state(PauseState::Running)),
+            )
+            .add_systems(
+                Update,
+                update_status_icons.run_if(in_state(GameState::Playing)),
             )
-                .chain()
-                .after(InputSet)
-                .run_if(in_state(GameState::Playing)),
-        )
-        .add_plugins(InputPlugin);
+            .add_plugins(InputPlugin);
     }
 }
 
+/// Attacks, movement, hits and deaths. Systems reacting to those in the same tick run after it.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CombatSet;
+
+/// Radius of the area a step damages, unless changed with [`Step::with_radius`]
+pub const STEP_RADIUS: f32 = 15.0;
+
+const ARROW_SPEED: f32 = 300.0;
+
+/// Chance of a hit to deal double damage
+const CRIT_CHANCE: f64 = 0.1;
+
 #[derive(Component, Deref, DerefMut)]
 pub struct Dying(pub Timer);
 
@@ -68,8 +103,14 @@ pub enum Actions {
 pub struct Step {
     pub timer: Timer,
     pub effect: Effect,
-    pub sfx: Option<Handle<AudioSource>>,
+    pub sfx: Option<Sound>,
     pub forward: f32,
+    /// Applied to everyone hit by this step
+    pub status: Option<StatusEffect>,
+    /// Of the area around the character this step damages, 0 to damage none
+    pub radius: f32,
+    /// Trauma of the camera shake when the step executes
+    pub shake: f32,
 }
 
 pub enum Effect {
@@ -81,6 +122,8 @@ pub enum Effect {
 
 pub enum Spawn {
     Arrow,
+    /// Flies until it hits an enemy
+    PlayerArrow,
 }
 
 impl Step {
@@ -90,6 +133,9 @@ impl Step {
             forward: 0.0,
             effect: Effect::None,
             sfx: default(),
+            status: None,
+            radius: STEP_RADIUS,
+            shake: 0.0,
         }
     }
 
@@ -101,12 +147,27 @@ impl Step {
         Self { forward, ..self }
     }
 
-    pub fn with_sfx(self, sfx: Handle<AudioSource>) -> Self {
+    pub fn with_sfx(self, sfx: Sound) -> Self {
         Self {
             sfx: Some(sfx),
             ..self
         }
     }
+
+    pub fn with_status(self, status: StatusEffect) -> Self {
+        Self {
+            status: Some(status),
+            ..self
+        }
+    }
+
+    pub fn with_radius(self, radius: f32) -> Self {
+        Self { radius, ..self }
+    }
+
+    pub fn with_shake(self, shake: f32) -> Self {
+        Self { shake, ..self }
+    }
 }
 
 #[derive(DerefMut, Deref)]
@@ -126,30 +187,61 @@ pub enum MoveMotion {
 }
 
 #[derive(Component)]
+#[require(StatusEffects)]
 pub struct Health {
     pub owner: u32,
     pub health: u32,
     pub max_health: u32,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Damage {
+    /// The character that caused the damage, it gets the score for kills
+    pub source: Entity,
     pub source_owner: u32,
     pub target_owner: u32,
+    pub status: Option<StatusEffect>,
+}
+
+/// Health lost by a character, from a hit or an effect
+#[derive(Event)]
+pub struct DamageEvent {
+    pub target: Entity,
+    /// The character that caused it, it gets the score for kills
+    pub source: Entity,
+    pub amount: u32,
+    pub knockback: Vec2,
+    /// Dealt more damage than usual
+    pub crit: bool,
+}
+
+/// A character's health ran out
+#[derive(Event)]
+pub struct DeathEvent {
+    pub position: Vec2,
+    /// What kind of enemy died, `None` for players
+    pub enemy: Option<EnemyForm>,
 }
 
 fn character_movement(
     time: Res<Time>,
-    mut character_query: Query<(Entity, &Movement, &mut MoveMotion)>,
+    mut character_query: Query<(Entity, &Movement, &mut MoveMotion, Option<&StatusEffects>)>,
     mut commands: Commands,
 ) {
-    for (character_entity, movement, mut move_motion) in character_query.iter_mut() {
+    for (character_entity, movement, mut move_motion, status_effects) in character_query.iter_mut()
+    {
+        if status_effects.is_some_and(StatusEffects::stunned) {
+            continue;
+        }
+        let speed_factor = status_effects.map_or(1.0, StatusEffects::speed_factor);
         if let Some(move_direction) = movement.move_direction {
             match *move_motion {
                 MoveMotion::Sliding { speed } => {
                     commands.entity(character_entity).insert(
-                        ExternalForce::new(move_direction.clamp_length(0.0, speed) * 800.0)
-                            .with_persistence(false),
+                        ExternalForce::new(
+                            move_direction.clamp_length(0.0, speed * speed_factor) * 800.0,
+                        )
+                        .with_persistence(false),
                     );
                 }
                 MoveMotion::Bouncing {
@@ -161,7 +253,7 @@ fn character_movement(
                         commands
                             .entity(character_entity)
                             .insert(ExternalImpulse::new(
-                                move_direction.clamp_length(0.0, speed) * 500.0,
+                                move_direction.clamp_length(0.0, speed * speed_factor) * 500.0,
                             ));
                     }
                 }
@@ -170,19 +262,30 @@ fn character_movement(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn character_actions(
     time: Res<Time>,
-    mut character_query: Query<(Entity, &Transform, &mut Actions, &Health)>,
+    mut character_query: Query<(Entity, &Transform, &mut Actions, &Health, &StatusEffects)>,
     mut player_query: Query<&mut Player>,
-    audio: Res<Audio>,
+    mut sfx_events: EventWriter<PlaySfx>,
     textures: Res<TextureAssets>,
     ranged_enemy_assets: Res<RangedEnemyAssets>,
     effect_assets: Res<EffectAssets>,
+    mut camera_impulses: EventWriter<CameraImpulse>,
     mut commands: Commands,
 ) {
-    for (character_entity, character_transform, mut actions, Health { owner, .. }) in
-        character_query.iter_mut()
+    for (
+        character_entity,
+        character_transform,
+        mut actions,
+        Health { owner, .. },
+        status_effects,
+    ) in character_query.iter_mut()
     {
+        // Everything waits until the stun is over
+        if status_effects.stunned() {
+            continue;
+        }
         let actions = &mut *actions;
         match actions {
             Actions::ChangePlayerForm(next_player_form) => {
@@ -201,7 +304,9 @@ fn character_actions(
                         });
                     }
                 }
-                player_query.single_mut().form = *next_player_form;
+                if let Ok(mut player) = player_query.get_mut(character_entity) {
+                    player.form = *next_player_form;
+                }
                 *actions = Actions::Idle;
             }
             Actions::Cooldown(timer) => {
@@ -215,76 +320,101 @@ fn character_actions(
                 pending_cooldown,
                 steps,
             } => {
-                if let Some(item) = steps.front_mut() {
-                    // TODO the time is 'cut off' here, some portion should be subtracted from the next
-                    // timer
-                    item.timer.tick(time.delta());
-                    if item.timer.finished() {
-                        let item = steps.pop_front().expect("No steps found");
-
-                        let mut transform = *character_transform;
-                        transform.translation += Vec3::Z;
-                        transform.rotation = Quat::from_rotation_z(trigger_direction.to_angle());
-                        let mut ec = commands.spawn((
-                            transform,
-                            OneShot::Despawn,
-                            Collider::circle(15.0),
-                            Damage {
-                                source_owner: *owner,
-                                target_owner: 1 - *owner,
-                            },
-                            Sensor,
-                        ));
-                        match item.effect {
-                            Effect::Circle => {
-                                ec.insert((
-                                    ParticleSpawner::default(),
-                                    ParticleEffectHandle(effect_assets.sword_slash.clone()),
-                                ));
-                            }
-                            Effect::Splash => {
-                                ec.insert((
-                                    ParticleSpawner::default(),
-                                    ParticleEffectHandle(effect_assets.enemy_1_attack.clone()),
-                                ));
-                            }
-                            Effect::Spawn(Spawn::Arrow) => {
-                                commands.spawn((
-                                    AseSpriteAnimation {
-                                        aseprite: ranged_enemy_assets.projectile.clone(),
-                                        animation: default(),
-                                    },
-                                    transform,
-                                    LinearVelocity(
-                                        trigger_direction.clamp_length(100000.0, 100000.0),
-                                    ),
-                                    Collider::circle(2.0),
-                                    Dying(Timer::from_seconds(0.7, TimerMode::Once)),
-                                    CollisionLayers::new(
-                                        CollisionLayer::EnemyProjectile,
-                                        LayerMask::NONE,
-                                        //[CollisionLayer::Default, CollisionLayer::Player],
-                                    ),
-                                    StateScoped(GameState::Playing),
-                                ));
-                            }
-                            Effect::None => (),
+                // Time left over by a finished step goes to the next one
+                let mut delta = time.delta();
+                while let Some(item) = steps.front_mut() {
+                    let remaining = item.timer.remaining();
+                    item.timer.tick(delta);
+                    if !item.timer.finished() {
+                        break;
+                    }
+                    delta = delta.saturating_sub(remaining);
+                    let item = steps.pop_front().expect("No steps found");
+
+                    let mut transform = *character_transform;
+                    transform.translation += Vec3::Z;
+                    transform.rotation = Quat::from_rotation_z(trigger_direction.to_angle());
+                    let damage = Damage {
+                        source: character_entity,
+                        source_owner: *owner,
+                        target_owner: 1 - *owner,
+                        status: item.status,
+                    };
+                    let mut ec = commands.spawn((transform, OneShot::Despawn));
+                    if item.radius > 0.0 {
+                        ec.insert((Collider::circle(item.radius), damage.clone(), Sensor));
+                    }
+                    match item.effect {
+                        Effect::Circle => {
+                            ec.insert((
+                                ParticleSpawner::default(),
+                                ParticleEffectHandle(effect_assets.sword_slash.clone()),
+                            ));
                         }
-                        if let Some(sfx) = item.sfx {
-                            audio.play(sfx.clone()).with_volume(0.2);
+                        Effect::Splash => {
+                            ec.insert((
+                                ParticleSpawner::default(),
+                                ParticleEffectHandle(effect_assets.enemy_1_attack.clone()),
+                            ));
                         }
-
-                        if item.forward != 0.0 {
-                            let character_direction =
-                                trigger_direction.normalize_or_zero() * item.forward * 1000.0;
-                            commands
-                                .entity(character_entity)
-                                .insert(ExternalImpulse::new(character_direction));
+                        Effect::Spawn(Spawn::Arrow) => {
+                            commands.spawn((
+                                AseSpriteAnimation {
+                                    aseprite: ranged_enemy_assets.projectile.clone(),
+                                    animation: default(),
+                                },
+                                transform,
+                                LinearVelocity(trigger_direction.clamp_length(100000.0, 100000.0)),
+                                Collider::circle(2.0),
+                                Dying(Timer::from_seconds(0.7, TimerMode::Once)),
+                                CollisionLayers::new(
+                                    CollisionLayer::EnemyProjectile,
+                                    LayerMask::NONE,
+                                    //[CollisionLayer::Default, CollisionLayer::Player],
+                                ),
+                                StateScoped(GameState::Playing),
+                            ));
+                        }
+                        Effect::Spawn(Spawn::PlayerArrow) => {
+                            commands.spawn((
+                                Sprite::from_image(textures.player_arrow.clone()),
+                                transform,
+                                RigidBody::Kinematic,
+                                LinearVelocity(trigger_direction.normalize_or_zero() * ARROW_SPEED),
+                                Collider::circle(2.0),
+                                Sensor,
+                                damage,
+                                Dying(Timer::from_seconds(1.0, TimerMode::Once)),
+                                CollisionLayers::new(
+                                    CollisionLayer::Default,
+                                    [CollisionLayer::Enemy],
+                                ),
+                                StateScoped(GameState::Playing),
+                            ));
                         }
+                        Effect::None => (),
+                    }
+                    if item.shake > 0.0 {
+                        camera_impulses.send(CameraImpulse::trauma(item.shake));
                     }
-                } else {
-                    *actions = Actions::Cooldown(pending_cooldown.clone());
-                };
+                    if let Some(sfx) = item.sfx {
+                        let position = character_transform.translation.xy();
+                        sfx_events.send(PlaySfx::new(sfx).at(position));
+                    }
+
+                    if item.forward != 0.0 {
+                        let character_direction =
+                            trigger_direction.normalize_or_zero() * item.forward * 1000.0;
+                        commands
+                            .entity(character_entity)
+                            .insert(ExternalImpulse::new(character_direction));
+                    }
+                }
+                if steps.is_empty() {
+                    let mut cooldown = pending_cooldown.clone();
+                    cooldown.tick(delta);
+                    *actions = Actions::Cooldown(cooldown);
+                }
             }
             _ => (),
         }
@@ -293,15 +423,12 @@ fn character_actions(
 
 fn hit_detection(
     mut collision_event_reader: EventReader<Collision>,
-    mut health_query: Query<(&Transform, &mut Health)>,
+    mut health_query: Query<(&Transform, &Health, &mut StatusEffects)>,
     damage_query: Query<(&Transform, &Damage)>,
-    mut player_query: Query<&mut Player>,
-    ai_query: Query<&Ai>,
-    audio: Res<Audio>,
-    audio_assets: Res<AudioAssets>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut camera_impulses: EventWriter<CameraImpulse>,
+    mut run_rng: ResMut<RunRng>,
     mut commands: Commands,
-    textures: Res<TextureAssets>,
-    ranged_enemy_assets: Res<RangedEnemyAssets>,
 ) {
     for Collision(contacts) in collision_event_reader.read() {
         let Contacts {
@@ -313,52 +440,30 @@ fn hit_detection(
             std::mem::swap(&mut entity1, &mut entity2);
         }
         if let (
-            Ok((
-                damage_source_transform,
-                Damage {
-                    target_owner,
-                    source_owner,
-                },
-            )),
-            Ok((target_transform, mut health)),
+            Ok((damage_source_transform, damage)),
+            Ok((target_transform, health, mut status_effects)),
         ) = (damage_query.get(entity1), health_query.get_mut(entity2))
         {
-            if *target_owner == health.owner && health.health > 0 {
-                health.health -= 1;
+            if damage.target_owner == health.owner && health.health > 0 {
                 let delta = target_transform.translation - damage_source_transform.translation;
                 let delta = delta.truncate().normalize_or_zero();
-                let mut ec = commands.entity(entity2);
-                ec.insert(ExternalImpulse::new(delta * 1000.0));
-                if health.health == 0 {
-                    if *source_owner == 0 {
-                        player_query.single_mut().score += 1;
-                    }
-                    if *target_owner == 0 {
-                        audio
-                            .play(audio_assets.player_damaged_effected.clone())
-                            .with_volume(0.3);
-                    }
-                    ec.insert(Dying(Timer::from_seconds(1.0, TimerMode::Once)));
-                    ec.remove::<(Ai, Health)>();
-
-                    if let Ok(ai) = ai_query.get(entity2) {
-                        let animation = Animation::default().with_repeat(0.into());
-                        match ai.form {
-                            EnemyForm::Melee => {
-                                ec.insert(AseSpriteAnimation {
-                                    aseprite: textures.enemy_1_death.clone(),
-                                    animation,
-                                });
-                            }
-                            EnemyForm::Ranged => {
-                                ec.insert(AseSpriteAnimation {
-                                    aseprite: ranged_enemy_assets.death.clone(),
-                                    animation,
-                                });
-                            }
-                        }
-                        ec.insert(AnimationState::default());
-                    }
+                // Only the players land crits
+                let crit =
+                    damage.source_owner == 0 && run_rng.stream("crits").gen_bool(CRIT_CHANCE);
+                damage_events.send(DamageEvent {
+                    target: entity2,
+                    source: damage.source,
+                    amount: if crit { 2 } else { 1 },
+                    knockback: delta * 1000.0,
+                    crit,
+                });
+                if health.owner == 0 {
+                    camera_impulses.send(CameraImpulse::trauma(0.35));
+                } else if crit {
+                    camera_impulses.send(CameraImpulse::trauma(0.2).with_zoom(0.03));
+                }
+                if let Some(status) = damage.status {
+                    status_effects.apply(status, damage.source);
                 }
                 commands.entity(entity1).remove::<Damage>();
             }
@@ -366,15 +471,96 @@ fn hit_detection(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+fn apply_damage(
+    mut damage_events: EventReader<DamageEvent>,
+    mut death_events: EventWriter<DeathEvent>,
+    mut camera_impulses: EventWriter<CameraImpulse>,
+    mut health_query: Query<(&mut Health, &Transform)>,
+    mut player_query: Query<(&mut Player, Option<&ScoreMultiplier>)>,
+    mut run_stats: ResMut<RunStats>,
+    ai_query: Query<&Ai>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    ranged_enemy_assets: Res<RangedEnemyAssets>,
+) {
+    for DamageEvent {
+        target,
+        source,
+        amount,
+        knockback,
+        ..
+    } in damage_events.read()
+    {
+        let Ok((mut health, transform)) = health_query.get_mut(*target) else {
+            continue;
+        };
+        // Already dead, i.e. hit twice in the same tick
+        if health.health == 0 {
+            continue;
+        }
+        health.health = health.health.saturating_sub(*amount);
+        let mut ec = commands.entity(*target);
+        if *knockback != Vec2::ZERO {
+            ec.insert(ExternalImpulse::new(*knockback));
+        }
+        if health.health > 0 {
+            continue;
+        }
+        death_events.send(DeathEvent {
+            position: transform.translation.xy(),
+            enemy: ai_query.get(*target).ok().map(|ai| ai.form),
+        });
+        if health.owner == 0 {
+            camera_impulses.send(CameraImpulse::trauma(0.7).with_zoom(0.1));
+            sfx_events.send(PlaySfx::new(Sound::PlayerDeath).at(transform.translation.xy()));
+            ec.insert(Dying(Timer::from_seconds(
+                PLAYER_DEATH_SECS,
+                TimerMode::Once,
+            )));
+        } else {
+            if let Ok((mut player, multiplier)) = player_query.get_mut(*source) {
+                player.score += multiplier.map_or(1, |multiplier| multiplier.factor);
+            }
+            run_stats.kills += 1;
+            ec.insert(Dying(Timer::from_seconds(1.0, TimerMode::Once)));
+        }
+        // Dead characters no longer act, their effects end
+        ec.remove::<(Ai, Health, Actions)>()
+            .insert(StatusEffects::default());
+
+        if let Ok(ai) = ai_query.get(*target) {
+            let animation = Animation::default().with_repeat(0.into());
+            match ai.form {
+                EnemyForm::Melee => {
+                    ec.insert(AseSpriteAnimation {
+                        aseprite: textures.enemy_1_death.clone(),
+                        animation,
+                    });
+                }
+                EnemyForm::Ranged => {
+                    ec.insert(AseSpriteAnimation {
+                        aseprite: ranged_enemy_assets.death.clone(),
+                        animation,
+                    });
+                }
+            }
+            ec.insert(AnimationState::default());
+        }
+    }
+}
+
 fn despawn_dead(
     mut commands: Commands,
-    mut dead_query: Query<(Entity, &mut Dying)>,
+    mut dead_query: Query<(Entity, &mut Dying, Has<Player>)>,
     time: Res<Time>,
 ) {
-    for (entity, mut dying) in dead_query.iter_mut() {
+    for (entity, mut dying, is_player) in dead_query.iter_mut() {
         dying.tick(time.delta());
-        if dying.finished() {
-            commands.entity(entity).despawn();
+        // The player stays around until the game over screen takes over
+        if dying.finished() && !is_player {
+            commands.entity(entity).despawn_recursive();
         }
     }
 }
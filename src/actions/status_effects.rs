@@ -0,0 +1,205 @@
+use bevy::prelude::*;
+
+use crate::actions::{DamageEvent, Health};
+
+/// At most this many poison stacks are active on a character
+pub const MAX_POISON_STACKS: usize = 3;
+
+/// Poison and burn deal their damage in this interval
+const DAMAGE_INTERVAL_SECS: f32 = 1.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StatusKind {
+    /// Damage every interval. Applying it again only refreshes the duration.
+    Burn,
+    /// Damage every interval per stack, up to [`MAX_POISON_STACKS`]
+    Poison,
+    /// Movement speed is multiplied by the factor. Only the strongest slow applies.
+    Slow(f32),
+    /// Can neither move nor act
+    Stun,
+}
+
+impl StatusKind {
+    fn deals_damage(self) -> bool {
+        matches!(self, StatusKind::Burn | StatusKind::Poison)
+    }
+
+    fn same_kind(self, other: StatusKind) -> bool {
+        std::mem::discriminant(&self) == std::mem::discriminant(&other)
+    }
+
+    /// Color of the icon shown over the affected character
+    pub fn color(self) -> Color {
+        match self {
+            StatusKind::Burn => Color::srgb(1.0, 0.45, 0.1),
+            StatusKind::Poison => Color::srgb(0.4, 0.9, 0.2),
+            StatusKind::Slow(_) => Color::srgb(0.4, 0.6, 1.0),
+            StatusKind::Stun => Color::srgb(1.0, 0.95, 0.3),
+        }
+    }
+}
+
+/// What an attack applies to the characters it hits, see [`crate::actions::Step::with_status`]
+#[derive(Clone, Copy, Debug)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    pub secs: f32,
+}
+
+impl StatusEffect {
+    pub fn new(kind: StatusKind, secs: f32) -> Self {
+        Self { kind, secs }
+    }
+}
+
+struct ActiveEffect {
+    kind: StatusKind,
+    /// Who applied it, they get the score if it kills
+    source: Entity,
+    duration: Timer,
+    damage_timer: Timer,
+}
+
+/// The timed effects on a character. Everything with [`Health`] has these.
+#[derive(Component, Default)]
+pub struct StatusEffects(Vec<ActiveEffect>);
+
+impl StatusEffects {
+    pub fn apply(&mut self, effect: StatusEffect, source: Entity) {
+        let duration = Timer::from_seconds(effect.secs, TimerMode::Once);
+        let active = ActiveEffect {
+            kind: effect.kind,
+            source,
+            duration,
+            damage_timer: Timer::from_seconds(DAMAGE_INTERVAL_SECS, TimerMode::Repeating),
+        };
+        if effect.kind == StatusKind::Poison {
+            let stacks = self
+                .0
+                .iter()
+                .filter(|e| e.kind == StatusKind::Poison)
+                .count();
+            if stacks >= MAX_POISON_STACKS {
+                // Replace the stack that would run out first
+                if let Some(shortest) = self
+                    .0
+                    .iter_mut()
+                    .filter(|e| e.kind == StatusKind::Poison)
+                    .min_by(|a, b| a.duration.remaining().cmp(&b.duration.remaining()))
+                {
+                    *shortest = active;
+                }
+                return;
+            }
+            self.0.push(active);
+            return;
+        }
+        let Some(existing) = self.0.iter_mut().find(|e| e.kind.same_kind(effect.kind)) else {
+            self.0.push(active);
+            return;
+        };
+        // No stacking: keep the longer duration and the stronger slow
+        if active.duration.remaining() > existing.duration.remaining() {
+            existing.duration = active.duration;
+            existing.source = source;
+        }
+        if let (StatusKind::Slow(existing_factor), StatusKind::Slow(factor)) =
+            (&mut existing.kind, effect.kind)
+        {
+            *existing_factor = existing_factor.min(factor);
+        }
+    }
+
+    pub fn stunned(&self) -> bool {
+        self.0.iter().any(|e| e.kind == StatusKind::Stun)
+    }
+
+    /// Factor of the movement speed
+    pub fn speed_factor(&self) -> f32 {
+        self.0
+            .iter()
+            .filter_map(|e| match e.kind {
+                StatusKind::Slow(factor) => Some(factor),
+                _ => None,
+            })
+            .fold(1.0, f32::min)
+    }
+
+    /// The active effects, poison once no matter how many stacks
+    pub fn kinds(&self) -> impl Iterator<Item = StatusKind> + '_ {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(i, e)| {
+                !self.0[..*i]
+                    .iter()
+                    .any(|other| other.kind.same_kind(e.kind))
+            })
+            .map(|(_, e)| e.kind)
+    }
+}
+
+/// Shows that an effect is active on its parent
+#[derive(Component)]
+pub struct StatusIcon;
+
+pub(super) fn tick_status_effects(
+    time: Res<Time>,
+    mut status_query: Query<(Entity, &mut StatusEffects), With<Health>>,
+    mut damage_events: EventWriter<DamageEvent>,
+) {
+    for (entity, mut status_effects) in status_query.iter_mut() {
+        // Only changes when effects run out, the icons are updated then
+        let effects = &mut status_effects.bypass_change_detection().0;
+        for effect in effects.iter_mut() {
+            effect.duration.tick(time.delta());
+            if !effect.kind.deals_damage() {
+                continue;
+            }
+            effect.damage_timer.tick(time.delta());
+            for _ in 0..effect.damage_timer.times_finished_this_tick() {
+                damage_events.send(DamageEvent {
+                    target: entity,
+                    source: effect.source,
+                    amount: 1,
+                    knockback: Vec2::ZERO,
+                    crit: false,
+                });
+            }
+        }
+        let count = effects.len();
+        effects.retain(|effect| !effect.duration.finished());
+        if effects.len() != count {
+            status_effects.set_changed();
+        }
+    }
+}
+
+/// Keeps a row of icons over every affected character in sync with its effects
+pub(super) fn update_status_icons(
+    mut commands: Commands,
+    status_query: Query<(Entity, &StatusEffects, Option<&Children>), Changed<StatusEffects>>,
+    icon_query: Query<&StatusIcon>,
+) {
+    for (entity, status_effects, children) in status_query.iter() {
+        for child in children.into_iter().flatten() {
+            if icon_query.contains(*child) {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+        commands.entity(entity).with_children(|commands| {
+            for (i, kind) in status_effects.kinds().enumerate() {
+                commands.spawn((
+                    StatusIcon,
+                    Sprite {
+                        color: kind.color(),
+                        custom_size: Some(Vec2::splat(3.0)),
+                        ..default()
+                    },
+                    Transform::from_xyz(-6.0 + i as f32 * 4.0, 10.0, 1.0),
+                ));
+            }
+        });
+    }
+}
@@ -1,11 +1,706 @@
+//! Everything plays on typed channels: [`Music`], [`Sfx`], [`Ui`] and [`Ambient`]. Their volumes
+//! come from the settings, the master volume included.
+//! Gameplay sends [`PlaySfx`] instead of playing sounds itself, how each [`Sound`] plays is set
+//! up in `Sound::spec`.
+//! Every game state has a playlist in `assets/music.playlists.ron`, switching states crossfades.
+//! While playing, the music follows the [`CombatIntensity`]. A heartbeat loops on the [`Ambient`]
+//! channel while a player is down to the last heart.
+//! Browsers only start audio after the first user interaction, `build/web/sound.js` resumes it.
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
 use bevy::prelude::*;
+use bevy::utils::HashMap;
 use bevy_kira_audio::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::intensity::CombatIntensity;
+use crate::loading::{AudioAssets, MusicAssets, RangedEnemyAssets};
+use crate::settings::Settings;
+use crate::GameState;
+
+/// How long the music of one state fades into the next, also between the tracks of a playlist
+const CROSSFADE_SECS: f64 = 2.0;
+/// The combat music starts above this [`CombatIntensity`], and stops below the other
+const COMBAT_ENTER_INTENSITY: f32 = 0.45;
+const COMBAT_LEAVE_INTENSITY: f32 = 0.25;
+/// Factor of the music volume while a player is down to the last heart, so the heartbeat stands out
+const LOW_HEALTH_MUSIC_VOLUME: f64 = 0.5;
+/// How long the music volume takes to change, also how long the heartbeat fades in and out
+const VOLUME_FADE_SECS: f64 = 0.5;
+const HEARTBEAT_VOLUME: f64 = 0.6;
+/// Sounds this far to the side of the camera, in world units, play as far left or right as they go
+const PAN_DISTANCE: f32 = 160.0;
+/// Panning goes from 0, left, to 1, right. Not all the way, it sounds odd in headphones.
+const MAX_PANNING: f32 = 0.3;
 
 pub struct InternalAudioPlugin;
 
 // This plugin is responsible to control the game audio
 impl Plugin for InternalAudioPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(AudioPlugin);
+        app.add_plugins(AudioPlugin)
+            .add_audio_channel::<Music>()
+            .add_audio_channel::<Sfx>()
+            .add_audio_channel::<Ui>()
+            .add_audio_channel::<Ambient>()
+            .add_event::<PlaySfx>()
+            .init_asset::<Playlists>()
+            .register_asset_loader(PlaylistsLoader)
+            .init_resource::<MusicPlayer>()
+            .init_resource::<PlayingSounds>()
+            .init_resource::<Heartbeat>()
+            .add_systems(
+                Update,
+                (
+                    play_music,
+                    play_heartbeat,
+                    play_sfx,
+                    set_volumes.run_if(resource_changed::<Settings>),
+                ),
+            );
+    }
+}
+
+/// Stands in for [`InternalAudioPlugin`] without an audio device.
+/// Sounds are accepted but never played.
+pub struct SilentAudioPlugin;
+
+impl Plugin for SilentAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AudioChannel<Music>>()
+            .init_resource::<AudioChannel<Sfx>>()
+            .init_resource::<AudioChannel<Ui>>()
+            .init_resource::<AudioChannel<Ambient>>()
+            .add_event::<PlaySfx>();
+    }
+}
+
+/// The audio channel of the music, its volume is the music volume of the settings
+#[derive(Resource)]
+pub struct Music;
+
+/// The audio channel of gameplay sounds, paused with the game
+#[derive(Resource)]
+pub struct Sfx;
+
+/// The audio channel of menu sounds, they play while paused too
+#[derive(Resource)]
+pub struct Ui;
+
+/// The audio channel of background loops like wind or water, paused with the game
+#[derive(Resource)]
+pub struct Ambient;
+
+/// Plays the sound, unless too many of it play already
+#[derive(Event)]
+pub struct PlaySfx {
+    pub sound: Sound,
+    /// Where in the world the sound is made, it's quieter further from the camera and panned to
+    /// the side it's on. Sounds without a position play the same everywhere.
+    pub position: Option<Vec2>,
+}
+
+impl PlaySfx {
+    pub fn new(sound: Sound) -> Self {
+        Self {
+            sound,
+            position: None,
+        }
     }
+
+    pub fn at(self, position: Vec2) -> Self {
+        Self {
+            position: Some(position),
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Sound {
+    Woosh,
+    MeleeEnemyAttack,
+    RangedEnemyAttack,
+    PlayerDeath,
+    Click,
+}
+
+/// How a [`Sound`] plays
+struct SoundSpec {
+    channel: SoundChannel,
+    volume: f64,
+    /// Random amount added to or taken from the volume every time it plays
+    volume_variation: f64,
+    /// Playback rate, which changes the pitch too
+    pitch: f64,
+    pitch_variation: f64,
+    /// Further ones don't play while this many play already
+    max_playing: usize,
+    /// How the volume goes down with the distance to the camera, if the sound has a position
+    attenuation: Option<Attenuation>,
+}
+
+/// Sounds play at full volume up to `near` world units from the camera, fading out until `far`
+#[derive(Clone, Copy)]
+struct Attenuation {
+    near: f32,
+    far: f32,
+}
+
+impl Attenuation {
+    fn factor(self, distance: f32) -> f32 {
+        let fade = ((distance - self.near) / (self.far - self.near).max(1.0)).clamp(0.0, 1.0);
+        (1.0 - fade) * (1.0 - fade)
+    }
+}
+
+enum SoundChannel {
+    Sfx,
+    Ui,
+}
+
+impl Sound {
+    fn spec(self) -> SoundSpec {
+        let sfx = SoundSpec {
+            channel: SoundChannel::Sfx,
+            volume: 0.2,
+            volume_variation: 0.05,
+            pitch: 1.0,
+            pitch_variation: 0.1,
+            max_playing: 4,
+            attenuation: Some(Attenuation {
+                near: 100.0,
+                far: 300.0,
+            }),
+        };
+        match self {
+            Sound::Woosh => sfx,
+            // Mostly off screen, with many of them around
+            Sound::MeleeEnemyAttack | Sound::RangedEnemyAttack => SoundSpec {
+                max_playing: 3,
+                attenuation: Some(Attenuation {
+                    near: 60.0,
+                    far: 220.0,
+                }),
+                ..sfx
+            },
+            Sound::PlayerDeath => SoundSpec {
+                volume: 0.3,
+                volume_variation: 0.0,
+                pitch_variation: 0.0,
+                max_playing: 2,
+                attenuation: Some(Attenuation {
+                    near: 200.0,
+                    far: 500.0,
+                }),
+                ..sfx
+            },
+            // A short, high woosh
+            Sound::Click => SoundSpec {
+                channel: SoundChannel::Ui,
+                volume: 0.15,
+                pitch: 1.8,
+                pitch_variation: 0.05,
+                max_playing: 2,
+                attenuation: None,
+                ..sfx
+            },
+        }
+    }
+
+    fn source(
+        self,
+        audio_assets: &AudioAssets,
+        ranged_enemy_assets: &RangedEnemyAssets,
+    ) -> Handle<AudioSource> {
+        match self {
+            Sound::Woosh | Sound::Click => audio_assets.woosh.clone(),
+            Sound::MeleeEnemyAttack => audio_assets.enemy_1_attack.clone(),
+            Sound::RangedEnemyAttack => ranged_enemy_assets.attack_sound.clone(),
+            Sound::PlayerDeath => audio_assets.player_damaged_effected.clone(),
+        }
+    }
+}
+
+/// The instances of every sound that may still play
+#[derive(Resource, Default)]
+struct PlayingSounds(HashMap<Sound, Vec<Handle<AudioInstance>>>);
+
+/// The music of every game state, playing has calm and combat music
+#[derive(Asset, TypePath)]
+pub struct Playlists {
+    pub menu: Playlist,
+    pub calm: Playlist,
+    pub combat: Playlist,
+    pub game_over: Playlist,
+}
+
+impl Playlists {
+    fn get(&self, key: MusicKey) -> &Playlist {
+        match key {
+            MusicKey::Menu => &self.menu,
+            MusicKey::Calm => &self.calm,
+            MusicKey::Combat => &self.combat,
+            MusicKey::GameOver => &self.game_over,
+        }
+    }
+}
+
+/// Plays the intro once, then loops through the tracks
+#[derive(Default)]
+pub struct Playlist {
+    pub intro: Option<Handle<AudioSource>>,
+    pub tracks: Vec<Handle<AudioSource>>,
+    /// Switching between calm and combat music waits for the next bar
+    pub bar_secs: Option<f64>,
+}
+
+impl Playlist {
+    fn is_empty(&self) -> bool {
+        self.intro.is_none() && self.tracks.is_empty()
+    }
+}
+
+/// A [`Playlist`] as written in the file, with paths instead of handles
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PlaylistPaths {
+    intro: Option<String>,
+    tracks: Vec<String>,
+    bpm: Option<f64>,
+    beats_per_bar: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct PlaylistsPaths {
+    menu: PlaylistPaths,
+    calm: PlaylistPaths,
+    combat: PlaylistPaths,
+    game_over: PlaylistPaths,
+}
+
+#[derive(Default)]
+struct PlaylistsLoader;
+
+#[derive(Debug, Error)]
+pub enum PlaylistsError {
+    #[error("Could not read playlists: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse playlists: {0}")]
+    Ron(#[from] ron::de::SpannedError),
+}
+
+impl AssetLoader for PlaylistsLoader {
+    type Asset = Playlists;
+    type Settings = ();
+    type Error = PlaylistsError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let paths: PlaylistsPaths = ron::de::from_bytes(&bytes)?;
+        let mut load = |paths: PlaylistPaths| Playlist {
+            intro: paths.intro.map(|path| load_context.load(path)),
+            tracks: paths
+                .tracks
+                .into_iter()
+                .map(|path| load_context.load(path))
+                .collect(),
+            bar_secs: paths
+                .bpm
+                .map(|bpm| 60.0 / bpm * paths.beats_per_bar.unwrap_or(4) as f64),
+        };
+        Ok(Playlists {
+            menu: load(paths.menu),
+            calm: load(paths.calm),
+            combat: load(paths.combat),
+            game_over: load(paths.game_over),
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["playlists.ron"]
+    }
+}
+
+/// Which playlist plays
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum MusicKey {
+    Menu,
+    Calm,
+    Combat,
+    GameOver,
+}
+
+/// What plays on the [`Music`] channel
+#[derive(Resource, Default)]
+struct MusicPlayer {
+    key: Option<MusicKey>,
+    current: Option<PlayingTrack>,
+    /// Index of the current track in the playlist, none during the intro
+    track: Option<usize>,
+    /// Of the current track when last checked, to find when a bar starts
+    last_position: f64,
+    /// Of the channel, the music volume of the settings lowered at low health. None until it was
+    /// first set, the channel plays at full volume before.
+    volume: Option<f64>,
+}
+
+struct PlayingTrack {
+    source: Handle<AudioSource>,
+    instance: Handle<AudioInstance>,
+}
+
+impl MusicPlayer {
+    fn fade_out(&mut self, audio_instances: &mut Assets<AudioInstance>, fade_secs: f64) {
+        if let Some(current) = self.current.take() {
+            if let Some(instance) = audio_instances.get_mut(&current.instance) {
+                instance.stop(AudioTween::linear(secs(fade_secs)));
+            }
+        }
+    }
+
+    fn start(
+        &mut self,
+        music: &AudioChannel<Music>,
+        source: &Handle<AudioSource>,
+        fade_in: Option<f64>,
+    ) {
+        let mut command = music.play(source.clone());
+        if let Some(fade_in) = fade_in {
+            command.fade_in(AudioTween::linear(secs(fade_in)));
+        }
+        self.current = Some(PlayingTrack {
+            source: source.clone(),
+            instance: command.handle(),
+        });
+        self.last_position = 0.0;
+    }
+
+    /// Crossfades into the start of the playlist
+    fn switch(
+        &mut self,
+        key: MusicKey,
+        playlist: &Playlist,
+        music: &AudioChannel<Music>,
+        audio_instances: &mut Assets<AudioInstance>,
+        fade_secs: f64,
+    ) {
+        self.key = Some(key);
+        self.fade_out(audio_instances, fade_secs);
+        self.track = None;
+        match (&playlist.intro, playlist.tracks.first()) {
+            (Some(intro), _) => self.start(music, intro, Some(fade_secs)),
+            (None, Some(first)) => {
+                self.track = Some(0);
+                self.start(music, first, Some(fade_secs));
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+fn secs(secs: f64) -> std::time::Duration {
+    std::time::Duration::from_secs_f64(secs)
+}
+
+/// Switches to the playlist of the current state and moves through it.
+/// While playing, it's the combat playlist when the fight gets intense.
+#[allow(clippy::too_many_arguments)]
+fn play_music(
+    state: Res<State<GameState>>,
+    settings: Res<Settings>,
+    intensity: Res<CombatIntensity>,
+    music_assets: Res<MusicAssets>,
+    playlists: Res<Assets<Playlists>>,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+    music: Res<AudioChannel<Music>>,
+    mut player: ResMut<MusicPlayer>,
+) {
+    let volume = if intensity.low_health {
+        settings.music_volume() * LOW_HEALTH_MUSIC_VOLUME
+    } else {
+        settings.music_volume()
+    };
+    match player.volume {
+        Some(current) if current == volume => {}
+        Some(_) => {
+            music
+                .set_volume(volume)
+                .fade_in(AudioTween::linear(secs(VOLUME_FADE_SECS)));
+        }
+        // Right away, the music must not blare before the saved volume fades in
+        None => {
+            music.set_volume(volume);
+        }
+    }
+    player.volume = Some(volume);
+
+    let Some(playlists) = playlists.get(&music_assets.playlists) else {
+        return;
+    };
+    let wanted = match state.get() {
+        GameState::Loading | GameState::LoadingFailed => None,
+        GameState::Menu => Some(MusicKey::Menu),
+        GameState::GameOver => Some(MusicKey::GameOver),
+        GameState::Playing => {
+            // Calms down a bit below where it got intense, so it doesn't switch back and forth
+            let threshold = if player.key == Some(MusicKey::Combat) {
+                COMBAT_LEAVE_INTENSITY
+            } else {
+                COMBAT_ENTER_INTENSITY
+            };
+            if intensity.value > threshold && !playlists.combat.is_empty() {
+                Some(MusicKey::Combat)
+            } else {
+                Some(MusicKey::Calm)
+            }
+        }
+    };
+    let Some(wanted) = wanted else {
+        player.fade_out(&mut audio_instances, CROSSFADE_SECS);
+        player.key = None;
+        return;
+    };
+
+    // Stopped instances are removed from the assets, only the channel still knows about them
+    let position =
+        player
+            .current
+            .as_ref()
+            .and_then(|current| match music.state(&current.instance) {
+                PlaybackState::Playing { position } => Some(position),
+                PlaybackState::Stopped => Some(f64::INFINITY),
+                // Still queued or paused
+                _ => None,
+            });
+
+    let Some(key) = player.key.filter(|key| *key == wanted) else {
+        // Calm and combat music change on the next bar, everything else right away
+        let bar_secs = player
+            .key
+            .filter(|key| {
+                matches!(key, MusicKey::Calm | MusicKey::Combat)
+                    && matches!(wanted, MusicKey::Calm | MusicKey::Combat)
+            })
+            .and_then(|key| playlists.get(key).bar_secs);
+        match (bar_secs, position) {
+            (Some(bar_secs), Some(position)) if position.is_finite() => {
+                if bar_started(player.last_position, position, bar_secs) {
+                    let fade_secs = bar_secs.min(CROSSFADE_SECS);
+                    player.switch(
+                        wanted,
+                        playlists.get(wanted),
+                        &music,
+                        &mut audio_instances,
+                        fade_secs,
+                    );
+                } else {
+                    player.last_position = position;
+                }
+            }
+            _ => player.switch(
+                wanted,
+                playlists.get(wanted),
+                &music,
+                &mut audio_instances,
+                CROSSFADE_SECS,
+            ),
+        }
+        return;
+    };
+
+    let playlist = playlists.get(key);
+    let Some(position) = position else {
+        return;
+    };
+    player.last_position = position;
+    let Some(source) = player
+        .current
+        .as_ref()
+        .map(|current| current.source.clone())
+    else {
+        return;
+    };
+    let duration = audio_sources.get(&source).map_or(f64::INFINITY, |source| {
+        source.sound.duration().as_secs_f64()
+    });
+    match next_track(player.track, playlist.tracks.len(), position, duration) {
+        Some(NextTrack::Start(next)) => {
+            player.current = None;
+            player.track = Some(next);
+            player.start(&music, &playlist.tracks[next], None);
+        }
+        Some(NextTrack::Crossfade { track, fade_secs }) => {
+            player.fade_out(&mut audio_instances, fade_secs);
+            player.track = Some(track);
+            player.start(&music, &playlist.tracks[track], Some(fade_secs));
+        }
+        None => {}
+    }
+}
+
+/// Where a playlist goes from the current track
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NextTrack {
+    /// Right where the intro ends
+    Start(usize),
+    /// Fading over from the current track before it ends
+    Crossfade { track: usize, fade_secs: f64 },
+}
+
+/// The track that comes next out of `tracks`, once it's time for it. `track` is the current one,
+/// none during the intro. The position is infinite once the current track stopped.
+pub fn next_track(
+    track: Option<usize>,
+    tracks: usize,
+    position: f64,
+    duration: f64,
+) -> Option<NextTrack> {
+    if tracks == 0 {
+        return None;
+    }
+    let next = track.map_or(0, |track| (track + 1) % tracks);
+    // Short tracks don't fade for longer than half of them
+    let fade_secs = CROSSFADE_SECS.min(duration / 2.0);
+    match track {
+        // The loop starts right where the intro ends
+        None if position.is_infinite() => Some(NextTrack::Start(next)),
+        Some(_) if position >= duration - fade_secs => Some(NextTrack::Crossfade {
+            track: next,
+            fade_secs,
+        }),
+        _ => None,
+    }
+}
+
+/// Whether a bar started after `last_position` of the track, up to `position`
+pub fn bar_started(last_position: f64, position: f64, bar_secs: f64) -> bool {
+    (position / bar_secs).floor() > (last_position / bar_secs).floor()
+}
+
+fn play_sfx(
+    mut sfx_events: EventReader<PlaySfx>,
+    audio_assets: Res<AudioAssets>,
+    ranged_enemy_assets: Res<RangedEnemyAssets>,
+    sfx: Res<AudioChannel<Sfx>>,
+    ui: Res<AudioChannel<Ui>>,
+    mut playing_sounds: ResMut<PlayingSounds>,
+    listener_query: Query<&GlobalTransform, With<Camera2d>>,
+) {
+    let mut rng = rand::thread_rng();
+    let listener = listener_query
+        .get_single()
+        .map(|transform| transform.translation().xy())
+        .ok();
+    for event in sfx_events.read() {
+        let spec = event.sound.spec();
+        // Relative to the listener, its volume factor and panning
+        let (attenuation, panning) = match (event.position, listener, spec.attenuation) {
+            (Some(position), Some(listener), Some(attenuation)) => {
+                let offset = position - listener;
+                let pan = (offset.x / PAN_DISTANCE).clamp(-1.0, 1.0) * MAX_PANNING;
+                (attenuation.factor(offset.length()), 0.5 + pan)
+            }
+            _ => (1.0, 0.5),
+        };
+        // Too far away to hear, it doesn't take the place of one that can be heard
+        if attenuation <= 0.0 {
+            continue;
+        }
+        let playing = playing_sounds.0.entry(event.sound).or_default();
+        // Stopped instances are removed from the assets, only the channel still knows about them
+        let stopped = |instance: &Handle<AudioInstance>| {
+            let state = match spec.channel {
+                SoundChannel::Sfx => sfx.state(instance),
+                SoundChannel::Ui => ui.state(instance),
+            };
+            matches!(state, PlaybackState::Stopped)
+        };
+        if !has_room(playing, spec.max_playing, stopped) {
+            continue;
+        }
+        let mut vary = |variation: f64| {
+            if variation > 0.0 {
+                rng.gen_range(-variation..variation)
+            } else {
+                0.0
+            }
+        };
+        let volume = (spec.volume + vary(spec.volume_variation)).max(0.0) * attenuation as f64;
+        let pitch = spec.pitch + vary(spec.pitch_variation);
+        let source = event.sound.source(&audio_assets, &ranged_enemy_assets);
+        let instance = match spec.channel {
+            SoundChannel::Sfx => sfx
+                .play(source)
+                .with_volume(volume)
+                .with_playback_rate(pitch)
+                .with_panning(panning as f64)
+                .handle(),
+            SoundChannel::Ui => ui
+                .play(source)
+                .with_volume(volume)
+                .with_playback_rate(pitch)
+                .with_panning(panning as f64)
+                .handle(),
+        };
+        playing.push(instance);
+    }
+}
+
+/// The heartbeat loop, while it plays
+#[derive(Resource, Default)]
+struct Heartbeat(Option<Handle<AudioInstance>>);
+
+/// Loops the heartbeat while a player is down to the last heart
+fn play_heartbeat(
+    intensity: Res<CombatIntensity>,
+    audio_assets: Res<AudioAssets>,
+    ambient: Res<AudioChannel<Ambient>>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+    mut heartbeat: ResMut<Heartbeat>,
+) {
+    if intensity.low_health && heartbeat.0.is_none() {
+        let instance = ambient
+            .play(audio_assets.heartbeat.clone())
+            .looped()
+            .with_volume(HEARTBEAT_VOLUME)
+            .linear_fade_in(secs(VOLUME_FADE_SECS))
+            .handle();
+        heartbeat.0 = Some(instance);
+    } else if !intensity.low_health {
+        let Some(instance) = heartbeat.0.take() else {
+            return;
+        };
+        if let Some(instance) = audio_instances.get_mut(&instance) {
+            instance.stop(AudioTween::linear(secs(VOLUME_FADE_SECS)));
+        }
+    }
+}
+
+/// Forgets the instances that stopped, then tells whether another one may start
+pub fn has_room<T>(playing: &mut Vec<T>, max_playing: usize, stopped: impl Fn(&T) -> bool) -> bool {
+    playing.retain(|instance| !stopped(instance));
+    playing.len() < max_playing
+}
+
+/// The music volume is set in [`play_music`], it's lowered at low health
+fn set_volumes(
+    settings: Res<Settings>,
+    sfx: Res<AudioChannel<Sfx>>,
+    ui: Res<AudioChannel<Ui>>,
+    ambient: Res<AudioChannel<Ambient>>,
+) {
+    sfx.set_volume(settings.sfx_volume());
+    ui.set_volume(settings.sfx_volume());
+    ambient.set_volume(settings.sfx_volume());
 }
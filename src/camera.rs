@@ -1,30 +1,392 @@
+use avian2d::prelude::LinearVelocity;
 use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use bevy::window::PrimaryWindow;
 
-use crate::player::Player;
+use crate::actions::Health;
+use crate::enemies::Ai;
+use crate::player::{Aim, Player};
+use crate::settings::Settings;
+use crate::GameState;
 
 pub struct CameraPlugin;
 
+/// World units kept visible around the outermost players
+const FRAME_MARGIN: f32 = 40.0;
+/// The window size the camera zoom setting is meant for. Other windows show the same world area,
+/// extended along one axis to match their aspect ratio.
+const REFERENCE_VIEW: Vec2 = Vec2::new(1280.0, 720.0);
+
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup_camera)
-            .add_systems(Update, update_camera);
+        app.init_resource::<CameraConfig>()
+            .add_event::<CameraImpulse>()
+            .add_systems(Startup, setup_camera)
+            .add_systems(OnEnter(GameState::Playing), reset_camera_follow)
+            .add_systems(OnExit(GameState::Playing), |mut commands: Commands| {
+                commands.remove_resource::<MapBounds>();
+            })
+            .add_systems(Update, (update_camera, shake_camera).chain());
+    }
+}
+
+/// How the camera follows the players
+#[derive(Resource)]
+pub struct CameraConfig {
+    /// Roughly the time it takes to catch up, without overshooting
+    pub smooth_time: f32,
+    /// Half the size of the area around the camera target the players can move in without moving
+    /// the camera, in world units
+    pub deadzone: Vec2,
+    /// How far ahead the camera looks in the direction the players aim or move
+    pub look_ahead: f32,
+    /// Limits of the zoom, as a factor of the world area the camera zoom setting shows. The
+    /// camera zooms out up to `max_scale` to frame its targets.
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// How fast the zoom approaches the one framing the targets
+    pub zoom_speed: f32,
+    /// Enemies this close to a player are framed too
+    pub threat_radius: f32,
+    /// Offset in world units at full trauma
+    pub shake_offset: f32,
+    /// Rotation in radians at full trauma
+    pub shake_angle: f32,
+    /// How fast the shake wobbles
+    pub shake_frequency: f32,
+    /// Trauma lost per second
+    pub trauma_decay: f32,
+    pub zoom_punches: bool,
+    /// Zoom punch lost per second
+    pub zoom_punch_decay: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            smooth_time: 0.15,
+            deadzone: Vec2::new(8.0, 6.0),
+            look_ahead: 24.0,
+            min_scale: 1.0,
+            max_scale: 2.5,
+            zoom_speed: 3.0,
+            threat_radius: 80.0,
+            shake_offset: 6.0,
+            shake_angle: 0.05,
+            shake_frequency: 20.0,
+            trauma_decay: 1.5,
+            zoom_punches: true,
+            zoom_punch_decay: 3.0,
+        }
+    }
+}
+
+/// Shakes the camera, e.g. for heavy hits. Nothing happens if screen shake is turned off.
+#[derive(Event, Default)]
+pub struct CameraImpulse {
+    /// Added to the trauma, which goes from 0 to 1. The shake grows with its square.
+    pub trauma: f32,
+    /// Zooms in by this fraction, then back out
+    pub zoom: f32,
+}
+
+impl CameraImpulse {
+    pub fn trauma(trauma: f32) -> Self {
+        Self { trauma, zoom: 0.0 }
+    }
+
+    pub fn with_zoom(self, zoom: f32) -> Self {
+        Self { zoom, ..self }
+    }
+}
+
+/// The area of the loaded map, the camera doesn't show beyond it
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct MapBounds {
+    pub center: Vec2,
+    pub half_size: Vec2,
+    /// Isometric maps are diamonds touching the middle of each side of the bounding box
+    pub diamond: bool,
+}
+
+impl MapBounds {
+    /// The closest camera position where a view of `half_view` is still inside
+    pub fn clamp(&self, position: Vec2, half_view: Vec2) -> Vec2 {
+        let offset = position - self.center;
+        if !self.diamond {
+            let limit = (self.half_size - half_view).max(Vec2::ZERO);
+            return self.center + offset.clamp(-limit, limit);
+        }
+        // The corners of the view must be inside |x| / a + |y| / b <= 1, so the center has to be
+        // inside a smaller diamond
+        let size = 1.0 - (half_view / self.half_size).element_sum();
+        if size <= 0.0 {
+            return self.center;
+        }
+        let normalized = offset.abs() / self.half_size;
+        if normalized.element_sum() <= size {
+            return position;
+        }
+        // Onto the closest point of the edge in this quadrant
+        let x = ((normalized.x - normalized.y + size) / 2.0).clamp(0.0, size);
+        let clamped = Vec2::new(x, size - x) * self.half_size * offset.signum();
+        self.center + clamped
     }
 }
 
+/// Added on top of where the camera follows to
+#[derive(Component, Default)]
+struct CameraShake {
+    trauma: f32,
+    zoom_punch: f32,
+    /// Moves along the noise
+    elapsed: f32,
+}
+
+/// Where the camera is going, its actual position is snapped to pixels
+#[derive(Component, Default)]
+struct CameraFollow {
+    position: Vec2,
+    velocity: Vec2,
+    target: Vec2,
+    /// Of the projection, before the zoom punches
+    scale: f32,
+    /// Jumps to the target on the next update instead of moving there
+    reset: bool,
+}
+
 fn setup_camera(mut commands: Commands) {
-    let mut projection = OrthographicProjection::default_2d();
-    projection.scale = 1.0 / 4.0;
-    commands.spawn((Camera2d, Msaa::Off, projection));
+    commands.spawn((
+        Camera2d,
+        Msaa::Off,
+        OrthographicProjection::default_2d(),
+        CameraFollow {
+            scale: 1.0,
+            reset: true,
+            ..default()
+        },
+        CameraShake::default(),
+    ));
+}
+
+fn reset_camera_follow(mut follow_query: Query<&mut CameraFollow>) {
+    for mut follow in follow_query.iter_mut() {
+        follow.reset = true;
+    }
 }
 
+/// Follows the players still alive and zooms out as far as needed to keep all of them in view,
+/// along with the enemies close to them if that doesn't zoom out too far
+#[allow(clippy::too_many_arguments)]
 fn update_camera(
-    player_query: Query<&Transform, With<Player>>,
-    mut camera_query: Query<&mut Transform, (Without<Player>, With<Camera>)>,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    config: Res<CameraConfig>,
+    map_bounds: Option<Res<MapBounds>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    player_query: Query<
+        (
+            &Transform,
+            Has<Health>,
+            Option<&Aim>,
+            Option<&LinearVelocity>,
+        ),
+        With<Player>,
+    >,
+    threat_query: Query<&Transform, (With<Ai>, With<Health>, Without<Camera>)>,
+    mut camera_query: Query<
+        (
+            &mut Transform,
+            &mut OrthographicProjection,
+            &mut CameraFollow,
+        ),
+        (Without<Player>, With<Camera>),
+    >,
+) {
+    let anyone_alive = player_query.iter().any(|(_, alive, ..)| alive);
+    // Once everyone died, keep looking at the death sequence
+    let followed = || {
+        player_query
+            .iter()
+            .filter(move |(_, alive, ..)| *alive || !anyone_alive)
+    };
+    let Ok((mut camera_transform, mut projection, mut follow)) = camera_query.get_single_mut()
+    else {
+        return;
+    };
+
+    // Keeps the same world area in view on any window, no matter its size or DPI
+    let min_view = REFERENCE_VIEW / settings.camera_zoom;
+    projection.scaling_mode = ScalingMode::AutoMin {
+        min_width: min_view.x,
+        min_height: min_view.y,
+    };
+    let window = window_query
+        .get_single()
+        .ok()
+        .filter(|window| window.width() > 0.0 && window.height() > 0.0);
+    let view = window.map_or(min_view, |window| base_view(window.size(), min_view));
+    // World units per physical pixel of the screen at a scale of 1
+    let pixel_size = window.map_or(1.0 / settings.camera_zoom, |window| {
+        view.y / window.physical_height() as f32
+    });
+
+    let positions = || followed().map(|(transform, ..)| transform.translation.xy());
+    let (Some(min), Some(max)) = (positions().reduce(Vec2::min), positions().reduce(Vec2::max))
+    else {
+        projection.scale = follow.scale;
+        camera_transform.translation =
+            snap(follow.position, pixel_size * follow.scale).extend(camera_transform.translation.z);
+        return;
+    };
+    let scale_for = |min: Vec2, max: Vec2| {
+        let span = max - min + 2.0 * FRAME_MARGIN;
+        (span / view).max_element().max(config.min_scale)
+    };
+    let threats = threat_query
+        .iter()
+        .map(|transform| transform.translation.xy())
+        .filter(|position| {
+            anyone_alive
+                && positions().any(|player| player.distance(*position) < config.threat_radius)
+        });
+    let (threat_min, threat_max) = threats.fold((min, max), |(min, max), position| {
+        (min.min(position), max.max(position))
+    });
+    // The players have to stay in view, the threats only when there's room
+    let (min, max) = if scale_for(threat_min, threat_max) <= config.max_scale {
+        (threat_min, threat_max)
+    } else {
+        (min, max)
+    };
+    let target_scale = scale_for(min, max).min(config.max_scale);
+
+    // Ahead of where the players are going, the dying ones aren't going anywhere
+    let directions: Vec<Vec2> = followed()
+        .filter(|(_, alive, ..)| *alive)
+        .filter_map(|(_, _, aim, velocity)| {
+            aim.and_then(|aim| aim.0).or_else(|| {
+                velocity
+                    .filter(|velocity| velocity.length() > 1.0)
+                    .map(|velocity| velocity.normalize())
+            })
+        })
+        .collect();
+    let look_ahead = if directions.is_empty() {
+        Vec2::ZERO
+    } else {
+        directions.iter().sum::<Vec2>() / directions.len() as f32 * config.look_ahead
+    };
+    let focus = (min + max) / 2.0 + look_ahead;
+
+    if follow.reset {
+        follow.reset = false;
+        follow.target = focus;
+        follow.position = focus;
+        follow.velocity = Vec2::ZERO;
+        follow.scale = target_scale;
+    }
+    // Only move the target once the focus leaves the deadzone around it
+    let offset = focus - follow.target;
+    follow.target += offset - offset.clamp(-config.deadzone, config.deadzone);
+
+    let delta_secs = time.delta_secs();
+    let (position, velocity) = smooth_damp(
+        follow.position,
+        follow.target,
+        follow.velocity,
+        config.smooth_time,
+        delta_secs,
+    );
+    follow.velocity = velocity;
+    follow.scale += (target_scale - follow.scale) * (1.0 - (-config.zoom_speed * delta_secs).exp());
+    projection.scale = follow.scale;
+    follow.position = match &map_bounds {
+        Some(bounds) => bounds.clamp(position, view * follow.scale / 2.0),
+        None => position,
+    };
+
+    camera_transform.translation =
+        snap(follow.position, pixel_size * follow.scale).extend(camera_transform.translation.z);
+}
+
+/// The world area shown at a scale of 1, at least `min_view` and with the aspect ratio of the
+/// window
+fn base_view(window_size: Vec2, min_view: Vec2) -> Vec2 {
+    if window_size.x * min_view.y > min_view.x * window_size.y {
+        Vec2::new(min_view.y * window_size.x / window_size.y, min_view.y)
+    } else {
+        Vec2::new(min_view.x, min_view.x * window_size.y / window_size.x)
+    }
+}
+
+/// To whole screen pixels, or the pixel art shimmers while the camera moves
+fn snap(position: Vec2, pixel_size: f32) -> Vec2 {
+    (position / pixel_size).round() * pixel_size
+}
+
+/// Runs after [`update_camera`], which sets the position and zoom this adds to every frame
+fn shake_camera(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    config: Res<CameraConfig>,
+    mut impulses: EventReader<CameraImpulse>,
+    mut camera_query: Query<(
+        &mut Transform,
+        &mut OrthographicProjection,
+        &mut CameraShake,
+    )>,
 ) {
-    let Ok(player_transform) = player_query.get_single() else {
+    let Ok((mut transform, mut projection, mut shake)) = camera_query.get_single_mut() else {
         return;
     };
-    let mut camera_transform = camera_query.single_mut();
+    for impulse in impulses.read() {
+        if settings.screen_shake {
+            shake.trauma = (shake.trauma + impulse.trauma).min(1.0);
+            if config.zoom_punches {
+                shake.zoom_punch = shake.zoom_punch.max(impulse.zoom);
+            }
+        }
+    }
+    if !settings.screen_shake {
+        shake.trauma = 0.0;
+        shake.zoom_punch = 0.0;
+    }
+    let delta = time.delta_secs();
+    shake.trauma = (shake.trauma - config.trauma_decay * delta).max(0.0);
+    shake.zoom_punch = (shake.zoom_punch - config.zoom_punch_decay * delta).max(0.0);
+    shake.elapsed += delta;
+
+    let amount = shake.trauma * shake.trauma;
+    let t = shake.elapsed * config.shake_frequency;
+    let offset = Vec2::new(wobble(t, 0.0), wobble(t, 10.0)) * config.shake_offset * amount;
+    transform.translation += offset.extend(0.0);
+    transform.rotation = Quat::from_rotation_z(wobble(t, 20.0) * config.shake_angle * amount);
+    projection.scale *= 1.0 - shake.zoom_punch;
+}
+
+/// Smooth noise from -1 to 1, `seed` gives different curves
+fn wobble(t: f32, seed: f32) -> f32 {
+    ((t + seed).sin() + (t * 1.7 + seed * 2.3).sin() * 0.5 + (t * 3.1 + seed * 0.7).sin() * 0.25)
+        / 1.75
+}
 
-    *camera_transform = *player_transform;
+/// Moves towards `target` like a critically damped spring, returns the new position and velocity
+fn smooth_damp(
+    position: Vec2,
+    target: Vec2,
+    velocity: Vec2,
+    smooth_time: f32,
+    delta_secs: f32,
+) -> (Vec2, Vec2) {
+    let omega = 2.0 / smooth_time.max(0.0001);
+    let x = omega * delta_secs;
+    // Approximates exp(-x)
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = position - target;
+    let temp = (velocity + omega * change) * delta_secs;
+    (
+        target + (change + temp) * decay,
+        (velocity - omega * temp) * decay,
+    )
 }
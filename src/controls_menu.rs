@@ -0,0 +1,298 @@
+use bevy::prelude::*;
+use bevy::ui::FocusPolicy;
+
+use crate::actions::{GameControl, InputBindings};
+use crate::loading::Fonts;
+use crate::menu::{spawn_button, ButtonColors, ChangeState};
+use crate::settings::SettingsState;
+use crate::storage::Storage;
+
+pub struct ControlsMenuPlugin;
+
+/// This plugin provides the screen to rebind the keyboard and gamepad controls.
+/// Clicking a binding waits for the next key or button, clicking it again cancels.
+impl Plugin for ControlsMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Rebinding>()
+            .add_systems(OnEnter(SettingsState::Controls), setup_controls_menu)
+            .add_systems(
+                OnExit(SettingsState::Controls),
+                (save_bindings, cancel_rebinding),
+            )
+            .add_systems(
+                Update,
+                (
+                    click_binding_buttons,
+                    click_reset_button,
+                    capture_binding,
+                    update_binding_labels,
+                )
+                    .chain()
+                    .run_if(in_state(SettingsState::Controls)),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Device {
+    Keyboard,
+    Gamepad,
+}
+
+/// Bindings per control and device that can be changed, further ones are shown in the last
+const SLOTS: usize = 2;
+
+/// The binding currently waiting for a key or button
+#[derive(Resource, Default)]
+struct Rebinding(Option<BindingButton>);
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+struct BindingButton {
+    control: GameControl,
+    device: Device,
+    slot: usize,
+}
+
+/// Shows the bindings of [`BindingButton`] it is a child of
+#[derive(Component)]
+struct BindingLabel(BindingButton);
+
+#[derive(Component)]
+struct ConflictLabel(GameControl);
+
+#[derive(Component)]
+struct ResetBindings;
+
+fn setup_controls_menu(mut commands: Commands, fonts: Res<Fonts>) {
+    let text_font = TextFont {
+        font: fonts.font.clone(),
+        font_size: 24.0,
+        ..default()
+    };
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(Color::linear_rgba(0.0, 0.0, 0.0, 0.85)),
+            // Clicks next to the buttons don't reach the menu underneath
+            FocusPolicy::Block,
+            GlobalZIndex(2),
+            StateScoped(SettingsState::Controls),
+        ))
+        .with_children(|children| {
+            children.spawn((
+                Text::new("Controls"),
+                TextFont {
+                    font_size: 64.0,
+                    ..text_font.clone()
+                },
+            ));
+            for control in GameControl::ALL {
+                spawn_control_row(children, control, &text_font);
+            }
+            spawn_button(children, "Reset", ResetBindings);
+            spawn_button(children, "Back", ChangeState(SettingsState::Open));
+        });
+}
+
+fn spawn_control_row(parent: &mut ChildBuilder, control: GameControl, font: &TextFont) {
+    parent
+        .spawn(Node {
+            width: Val::Px(920.0),
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Px(2.0)),
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn((
+                Text::new(format!("{control:?}")),
+                font.clone(),
+                Node {
+                    width: Val::Px(160.0),
+                    ..default()
+                },
+            ));
+            for device in [Device::Keyboard, Device::Gamepad] {
+                for slot in 0..SLOTS {
+                    spawn_binding_button(
+                        row,
+                        BindingButton {
+                            control,
+                            device,
+                            slot,
+                        },
+                        font,
+                    );
+                }
+            }
+            row.spawn((
+                Text::default(),
+                font.clone(),
+                TextColor(Color::linear_rgb(0.9, 0.2, 0.2)),
+                ConflictLabel(control),
+            ));
+        });
+}
+
+fn spawn_binding_button(row: &mut ChildBuilder, binding: BindingButton, font: &TextFont) {
+    let button_colors = ButtonColors::default();
+    row.spawn((
+        Button,
+        Node {
+            width: Val::Px(150.0),
+            height: Val::Px(36.0),
+            margin: UiRect::horizontal(Val::Px(4.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(button_colors.normal),
+        button_colors,
+        binding,
+    ))
+    .with_child((Text::default(), font.clone(), BindingLabel(binding)));
+}
+
+fn click_binding_buttons(
+    mut rebinding: ResMut<Rebinding>,
+    interaction_query: Query<(&Interaction, &BindingButton), (Changed<Interaction>, With<Button>)>,
+) {
+    for (interaction, button) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            rebinding.0 = if rebinding.0 == Some(*button) {
+                None
+            } else {
+                Some(*button)
+            };
+        }
+    }
+}
+
+fn click_reset_button(
+    mut bindings: ResMut<InputBindings>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<ResetBindings>)>,
+) {
+    if interaction_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        *bindings = default();
+    }
+}
+
+/// Replaces the binding waiting in [`Rebinding`] with the next pressed key or button, the other
+/// bindings of the control stay
+fn capture_binding(
+    mut rebinding: ResMut<Rebinding>,
+    mut bindings: ResMut<InputBindings>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepad_input: Query<&Gamepad>,
+) {
+    let Some(BindingButton {
+        control,
+        device,
+        slot,
+    }) = rebinding.0
+    else {
+        return;
+    };
+    match device {
+        Device::Keyboard => {
+            if let Some(key) = keyboard_input.get_just_pressed().next() {
+                bindings.bind_key(control, slot, *key);
+                rebinding.0 = None;
+            }
+        }
+        Device::Gamepad => {
+            if let Some(button) = gamepad_input
+                .iter()
+                .find_map(|g| g.get_just_pressed().next().copied())
+            {
+                bindings.bind_button(control, slot, button);
+                rebinding.0 = None;
+            }
+        }
+    }
+}
+
+fn update_binding_labels(
+    bindings: Res<InputBindings>,
+    rebinding: Res<Rebinding>,
+    mut binding_labels: Query<
+        (Ref<BindingLabel>, &mut Text, &mut TextColor),
+        Without<ConflictLabel>,
+    >,
+    mut conflict_labels: Query<(Ref<ConflictLabel>, &mut Text), Without<BindingLabel>>,
+) {
+    let changed = bindings.is_changed() || rebinding.is_changed();
+    for (label, mut text, mut color) in binding_labels.iter_mut() {
+        if !changed && !label.is_added() {
+            continue;
+        }
+        let BindingButton {
+            control,
+            device,
+            slot,
+        } = label.0;
+        text.0 = if rebinding.0 == Some(label.0) {
+            "...".to_string()
+        } else {
+            match device {
+                Device::Keyboard => slot_label(bindings.keys(control), slot),
+                Device::Gamepad => slot_label(bindings.buttons(control), slot),
+            }
+        };
+        color.0 = if bindings.conflicts(control).is_empty() {
+            Color::linear_rgb(0.9, 0.9, 0.9)
+        } else {
+            Color::linear_rgb(0.9, 0.2, 0.2)
+        };
+    }
+    for (label, mut text) in conflict_labels.iter_mut() {
+        if !changed && !label.is_added() {
+            continue;
+        }
+        let conflicts = bindings.conflicts(label.0);
+        text.0 = if conflicts.is_empty() {
+            String::new()
+        } else {
+            format!("Conflicts with {}", join(&conflicts))
+        };
+    }
+}
+
+/// The binding in the slot, the last one also shows any further bindings
+fn slot_label<T: std::fmt::Debug>(bound: &[T], slot: usize) -> String {
+    let bound = if slot + 1 == SLOTS {
+        bound.get(slot..).unwrap_or_default()
+    } else {
+        bound.get(slot..=slot).unwrap_or_default()
+    };
+    if bound.is_empty() {
+        return "-".to_string();
+    }
+    join(bound)
+}
+
+fn join<T: std::fmt::Debug>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| format!("{item:?}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn save_bindings(bindings: Res<InputBindings>, storage: Res<Storage>) {
+    bindings.save(&storage);
+}
+
+fn cancel_rebinding(mut rebinding: ResMut<Rebinding>) {
+    rebinding.0 = None;
+}
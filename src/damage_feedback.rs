@@ -0,0 +1,213 @@
+use bevy::math::vec2;
+use bevy::prelude::*;
+
+use crate::actions::{DamageEvent, Health};
+use crate::enemies::Ai;
+use crate::loading::Fonts;
+use crate::settings::Settings;
+use crate::GameState;
+
+/// Damage numbers are reused, at most this many are shown at once
+const DAMAGE_NUMBER_POOL_SIZE: usize = 32;
+const DAMAGE_NUMBER_SECS: f32 = 0.8;
+/// A health bar stays this long after the last hit, then fades out
+const HEALTH_BAR_SECS: f32 = 3.0;
+const HEALTH_BAR_FADE_SECS: f32 = 1.0;
+const HEALTH_BAR_SIZE: Vec2 = Vec2::new(12.0, 2.0);
+
+pub struct DamageFeedbackPlugin;
+
+/// This plugin shows health bars over damaged enemies and floating numbers for every hit.
+/// It can be turned off in the settings.
+impl Plugin for DamageFeedbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Playing), spawn_damage_number_pool)
+            .add_systems(
+                Update,
+                (
+                    (show_damage_numbers, show_health_bars)
+                        .run_if(|settings: Res<Settings>| settings.damage_feedback),
+                    animate_damage_numbers,
+                    update_health_bars,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// One of the pooled numbers, hidden while `timer` is finished
+#[derive(Component)]
+struct DamageNumber {
+    timer: Timer,
+    origin: Vec3,
+}
+
+#[derive(Component)]
+struct HealthBar {
+    timer: Timer,
+}
+
+#[derive(Component)]
+struct HealthBarFill;
+
+fn spawn_damage_number_pool(mut commands: Commands, fonts: Res<Fonts>) {
+    for _ in 0..DAMAGE_NUMBER_POOL_SIZE {
+        let mut timer = Timer::from_seconds(DAMAGE_NUMBER_SECS, TimerMode::Once);
+        timer.tick(timer.duration());
+        commands.spawn((
+            DamageNumber {
+                timer,
+                origin: Vec3::ZERO,
+            },
+            Text2d::default(),
+            TextFont {
+                font: fonts.font.clone(),
+                font_size: 64.0,
+                ..default()
+            },
+            Transform::default(),
+            Visibility::Hidden,
+            StateScoped(GameState::Playing),
+        ));
+    }
+}
+
+fn show_damage_numbers(
+    mut damage_events: EventReader<DamageEvent>,
+    target_query: Query<(&GlobalTransform, Has<Health>)>,
+    mut number_query: Query<(
+        &mut DamageNumber,
+        &mut Text2d,
+        &mut TextColor,
+        &mut Visibility,
+    )>,
+) {
+    for event in damage_events.read() {
+        let Ok((target_transform, alive)) = target_query.get(event.target) else {
+            continue;
+        };
+        // Take a free number, or the one shown the longest
+        let Some((mut number, mut text, mut color, mut visibility)) = number_query
+            .iter_mut()
+            .max_by(|(a, ..), (b, ..)| a.timer.elapsed().cmp(&b.timer.elapsed()))
+        else {
+            return;
+        };
+        number.timer.reset();
+        number.origin = target_transform.translation() + Vec3::new(0.0, 8.0, 10.0);
+        text.0 = if event.crit {
+            format!("{}!", event.amount)
+        } else {
+            event.amount.to_string()
+        };
+        color.0 = if !alive {
+            Color::srgb(1.0, 0.2, 0.2)
+        } else if event.crit {
+            Color::srgb(1.0, 0.85, 0.1)
+        } else {
+            Color::WHITE
+        };
+        *visibility = Visibility::Inherited;
+    }
+}
+
+fn animate_damage_numbers(
+    time: Res<Time>,
+    mut number_query: Query<(
+        &mut DamageNumber,
+        &mut Transform,
+        &mut TextColor,
+        &mut Visibility,
+    )>,
+) {
+    for (mut number, mut transform, mut color, mut visibility) in number_query.iter_mut() {
+        if number.timer.finished() {
+            continue;
+        }
+        number.timer.tick(time.delta());
+        if number.timer.finished() {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+        let progress = number.timer.fraction();
+        // Workaround to make Bevy not blur the font, like the cool-down display
+        *transform = Transform::from_translation(number.origin + Vec3::Y * progress * 10.0)
+            .with_scale(Vec3::splat(1.0 / 4.0));
+        color.0.set_alpha(1.0 - progress * progress);
+    }
+}
+
+fn show_health_bars(
+    mut commands: Commands,
+    mut damage_events: EventReader<DamageEvent>,
+    enemy_query: Query<Option<&Children>, (With<Ai>, With<Health>)>,
+    mut bar_query: Query<&mut HealthBar>,
+) {
+    for event in damage_events.read() {
+        let Ok(children) = enemy_query.get(event.target) else {
+            continue;
+        };
+        let mut bars = bar_query.iter_many_mut(children.into_iter().flatten());
+        if let Some(mut bar) = bars.fetch_next() {
+            bar.timer.reset();
+            continue;
+        }
+        commands.entity(event.target).with_children(|commands| {
+            commands
+                .spawn((
+                    HealthBar {
+                        timer: Timer::from_seconds(HEALTH_BAR_SECS, TimerMode::Once),
+                    },
+                    Sprite {
+                        color: Color::srgb(0.2, 0.0, 0.0),
+                        custom_size: Some(HEALTH_BAR_SIZE),
+                        ..default()
+                    },
+                    Transform::from_xyz(0.0, 9.0, 1.0),
+                ))
+                .with_child((
+                    HealthBarFill,
+                    Sprite {
+                        color: Color::srgb(0.9, 0.1, 0.1),
+                        custom_size: Some(HEALTH_BAR_SIZE),
+                        ..default()
+                    },
+                    Transform::from_xyz(0.0, 0.0, 0.1),
+                ));
+        });
+    }
+}
+
+fn update_health_bars(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut bar_query: Query<(Entity, &Parent, &mut HealthBar, &mut Sprite, &Children)>,
+    mut fill_query: Query<(&mut Sprite, &mut Transform), (With<HealthBarFill>, Without<HealthBar>)>,
+    health_query: Query<&Health>,
+) {
+    for (bar_entity, parent, mut bar, mut sprite, children) in bar_query.iter_mut() {
+        bar.timer.tick(time.delta());
+        let Ok(health) = health_query.get(parent.get()) else {
+            // Dead
+            commands.entity(bar_entity).despawn_recursive();
+            continue;
+        };
+        if bar.timer.finished() || !settings.damage_feedback {
+            commands.entity(bar_entity).despawn_recursive();
+            continue;
+        }
+        let alpha = (bar.timer.remaining_secs() / HEALTH_BAR_FADE_SECS).min(1.0);
+        sprite.color.set_alpha(alpha);
+        let fraction = health.health as f32 / health.max_health.max(1) as f32;
+        for child in children.iter() {
+            if let Ok((mut fill_sprite, mut fill_transform)) = fill_query.get_mut(*child) {
+                fill_sprite.color.set_alpha(alpha);
+                fill_sprite.custom_size = Some(HEALTH_BAR_SIZE * vec2(fraction, 1.0));
+                // Keep it left aligned
+                fill_transform.translation.x = -HEALTH_BAR_SIZE.x * (1.0 - fraction) / 2.0;
+            }
+        }
+    }
+}
@@ -1,14 +1,17 @@
-use crate::actions::{Actions, Effect, Health, MoveMotion, Movement, Spawn, Step};
-use crate::loading::{AudioAssets, RangedEnemyAssets, TextureAssets};
+use crate::actions::{
+    Actions, Effect, Health, InputSet, MoveMotion, Movement, Spawn, StatusEffect, StatusKind, Step,
+};
+use crate::audio::Sound;
+use crate::loading::{RangedEnemyAssets, TextureAssets};
 use crate::player::Player;
-use crate::GameState;
+use crate::seed::RunRng;
+use crate::{GameState, PauseState};
 
 use crate::physics::CollisionLayer;
 use avian2d::prelude::{Collider, CollisionLayers, LockedAxes};
-use bevy::math::vec3;
+use bevy::math::vec2;
 use bevy::prelude::*;
 use bevy_aseprite_ultra::prelude::*;
-use bevy_rand::prelude::{GlobalEntropy, WyRand};
 use rand::Rng;
 
 pub struct EnemiesPlugin;
@@ -18,28 +21,50 @@ pub struct Ai {
     pub form: EnemyForm,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
 pub enum EnemyForm {
     #[default]
     Melee,
     Ranged,
 }
 
+/// Spawn progress of each enemy type: `(accumulated, threshold)`.
+/// Reset whenever a new run starts.
+#[derive(Resource, Default)]
+struct SpawnCounters {
+    melee: (f32, f32),
+    ranged: (f32, f32),
+}
+
+/// The systems that keep spawning enemies while playing, in `FixedUpdate`
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnemySpawnSet;
+
 impl Plugin for EnemiesPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            PreUpdate,
-            (enemy_melee_spawner, enemy_ranged_spawner, ai_think)
-                .chain()
-                .run_if(in_state(GameState::Playing)),
-        )
-        .add_systems(
-            PostUpdate,
-            update_sprite.run_if(in_state(GameState::Playing)),
-        );
+        app.init_resource::<SpawnCounters>()
+            .add_systems(OnEnter(GameState::Playing), reset_spawn_counters)
+            .add_systems(
+                FixedUpdate,
+                (
+                    (enemy_melee_spawner, enemy_ranged_spawner).in_set(EnemySpawnSet),
+                    ai_think,
+                )
+                    .chain()
+                    .before(InputSet)
+                    .run_if(in_state(PauseState::Running)),
+            )
+            .add_systems(
+                PostUpdate,
+                update_sprite.run_if(in_state(GameState::Playing)),
+            );
     }
 }
 
+fn reset_spawn_counters(mut counters: ResMut<SpawnCounters>) {
+    *counters = default();
+}
+
 fn enemy_layer() -> CollisionLayers {
     CollisionLayers::new(
         CollisionLayer::Enemy,
@@ -49,11 +74,13 @@ fn enemy_layer() -> CollisionLayers {
 
 fn enemy_ranged_spawner(
     mut commands: Commands,
-    mut rng: GlobalEntropy<WyRand>,
-    mut enemy_counter: Local<(f32, f32)>,
+    mut run_rng: ResMut<RunRng>,
+    mut counters: ResMut<SpawnCounters>,
     ranged_enemy_assets: Res<RangedEnemyAssets>,
     time: Res<Time>,
 ) {
+    let rng = run_rng.stream("ranged_spawns");
+    let enemy_counter = &mut counters.ranged;
     if enemy_counter.1 == 0.0 {
         enemy_counter.1 = 3.0;
     }
@@ -64,17 +91,24 @@ fn enemy_ranged_spawner(
         enemy_counter.1 += 0.2;
         enemy_counter.0 -= enemy_counter.0;
 
-        commands.spawn((
+        let position = vec2(rng.gen_range(-200.0..200.0), rng.gen_range(-200.0..200.0));
+        spawn_ranged_enemy(&mut commands, &ranged_enemy_assets, position);
+    }
+}
+
+pub fn spawn_ranged_enemy(
+    commands: &mut Commands,
+    ranged_enemy_assets: &RangedEnemyAssets,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn((
             AseSpriteAnimation {
                 aseprite: ranged_enemy_assets.walk_left.clone(),
                 animation: Animation::default(),
             },
             enemy_layer(),
-            Transform::from_translation(vec3(
-                rng.gen_range(-200.0..200.0),
-                rng.gen_range(-200.0..200.0),
-                5.0,
-            )),
+            Transform::from_translation(position.extend(5.0)),
             Ai {
                 form: EnemyForm::Ranged,
             },
@@ -89,34 +123,43 @@ fn enemy_ranged_spawner(
             Actions::default(),
             Movement::default(),
             StateScoped(GameState::Playing),
-        ));
-    }
+        ))
+        .id()
 }
 
 fn enemy_melee_spawner(
     mut commands: Commands,
-    mut rng: GlobalEntropy<WyRand>,
-    mut enemy_counter: Local<(f32, f32)>,
+    mut run_rng: ResMut<RunRng>,
+    mut counters: ResMut<SpawnCounters>,
     textures: Res<TextureAssets>,
     time: Res<Time>,
 ) {
+    let rng = run_rng.stream("melee_spawns");
+    let enemy_counter = &mut counters.melee;
     enemy_counter.0 += rng.gen::<f32>() * time.delta_secs();
 
     if enemy_counter.0 > enemy_counter.1 {
         enemy_counter.1 += 0.1;
         enemy_counter.0 -= enemy_counter.0;
 
-        commands.spawn((
+        let position = vec2(rng.gen_range(-200.0..200.0), rng.gen_range(-200.0..200.0));
+        spawn_melee_enemy(&mut commands, &textures, position);
+    }
+}
+
+pub fn spawn_melee_enemy(
+    commands: &mut Commands,
+    textures: &TextureAssets,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn((
             AseSpriteAnimation {
                 aseprite: textures.enemy_1_left.clone(),
                 animation: Animation::default(),
             },
             enemy_layer(),
-            Transform::from_translation(vec3(
-                rng.gen_range(-200.0..200.0),
-                rng.gen_range(-200.0..200.0),
-                5.0,
-            )),
+            Transform::from_translation(position.extend(5.0)),
             Ai::default(),
             Collider::circle(5.0),
             Health {
@@ -132,23 +175,27 @@ fn enemy_melee_spawner(
             Actions::default(),
             Movement::default(),
             StateScoped(GameState::Playing),
-        ));
-    }
+        ))
+        .id()
 }
 
 fn ai_think(
     mut ai_query: Query<(&mut Movement, &mut Actions, &Transform, &Ai)>,
-    player_query: Query<&Transform, With<Player>>,
-    audio_assets: Res<AudioAssets>,
-    ranged_enemy_assets: Res<RangedEnemyAssets>,
+    player_query: Query<&Transform, (With<Player>, With<Health>)>,
 ) {
-    let Ok(player_transform) = player_query.get_single() else {
-        debug!("No player found");
-        return;
-    };
     for (mut movement, mut actions, ai_transform, ai) in ai_query.iter_mut() {
         let actions = &mut *actions;
-        let delta = (player_transform.translation - ai_transform.translation).truncate();
+        // Go for the closest player still alive
+        let Some(delta) = player_query
+            .iter()
+            .map(|player_transform| {
+                (player_transform.translation - ai_transform.translation).truncate()
+            })
+            .min_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+        else {
+            movement.move_direction = None;
+            continue;
+        };
         let range = match ai.form {
             EnemyForm::Melee => 15.0,
             EnemyForm::Ranged => 64.0,
@@ -164,7 +211,8 @@ fn ai_think(
                         pending_cooldown: Timer::from_seconds(0.5, TimerMode::Once),
                         steps: [Step::from_timer(Timer::from_seconds(1.2, TimerMode::Once))
                             .with_effect(Effect::Splash)
-                            .with_sfx(audio_assets.enemy_1_attack.clone())]
+                            .with_status(StatusEffect::new(StatusKind::Slow(0.5), 1.5))
+                            .with_sfx(Sound::MeleeEnemyAttack)]
                         .into(),
                     },
                     EnemyForm::Ranged => Actions::Executing {
@@ -172,7 +220,8 @@ fn ai_think(
                         pending_cooldown: Timer::from_seconds(0.8, TimerMode::Once),
                         steps: [Step::from_timer(Timer::from_seconds(1.3, TimerMode::Once))
                             .with_effect(Effect::Spawn(Spawn::Arrow))
-                            .with_sfx(ranged_enemy_assets.attack_sound.clone())]
+                            .with_status(StatusEffect::new(StatusKind::Poison, 3.0))
+                            .with_sfx(Sound::RangedEnemyAttack)]
                         .into(),
                     },
                 };
@@ -0,0 +1,169 @@
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+use crate::actions::{Dying, Health};
+use crate::high_scores::{spawn_high_score_table, HighScore, HighScores, MAX_HIGH_SCORES};
+use crate::loading::Fonts;
+use crate::menu::{spawn_button, ChangeState};
+use crate::player::Player;
+use crate::replay::Playback;
+use crate::seed::{today, RunSeed};
+use crate::storage::Storage;
+use crate::GameState;
+
+/// How long the player death sequence plays before the summary is shown
+pub const PLAYER_DEATH_SECS: f32 = 2.0;
+
+pub struct GameOverPlugin;
+
+/// This plugin plays the death sequence of the player and shows the run summary afterward.
+/// Restarting simply re-enters `GameState::Playing`, everything of the last run is `StateScoped`.
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        let high_scores = HighScores::load(app.world().resource::<Storage>());
+        app.init_resource::<RunStats>()
+            .insert_resource(high_scores)
+            .add_systems(OnEnter(GameState::Playing), reset_run_stats)
+            .add_systems(
+                Update,
+                (track_time_survived, player_death_sequence).run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnEnter(GameState::GameOver), setup_game_over);
+    }
+}
+
+/// Statistics of the current (or last) run
+#[derive(Resource, Default)]
+pub struct RunStats {
+    pub score: u32,
+    pub kills: u32,
+    pub time_survived: f32,
+}
+
+fn reset_run_stats(mut run_stats: ResMut<RunStats>) {
+    *run_stats = default();
+}
+
+fn track_time_survived(
+    time: Res<Time>,
+    mut run_stats: ResMut<RunStats>,
+    alive_query: Query<(), (With<Player>, With<Health>)>,
+) {
+    if !alive_query.is_empty() {
+        run_stats.time_survived += time.delta_secs();
+    }
+}
+
+/// Spin, shrink and fade out dead players. Once nobody is left, record the run and switch to the
+/// summary.
+#[allow(clippy::too_many_arguments)]
+fn player_death_sequence(
+    mut dying_query: Query<(&Dying, &mut Transform, Option<&mut Sprite>), With<Player>>,
+    player_query: Query<(&Player, Option<&Dying>)>,
+    mut run_stats: ResMut<RunStats>,
+    mut high_scores: ResMut<HighScores>,
+    storage: Res<Storage>,
+    run_seed: Res<RunSeed>,
+    playback: Option<Res<Playback>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for (dying, mut transform, sprite) in dying_query.iter_mut() {
+        let progress = dying.fraction();
+        transform.rotation = Quat::from_rotation_z(progress * progress * 2.0 * TAU);
+        transform.scale = Vec3::splat(1.0 - 0.8 * progress);
+        if let Some(mut sprite) = sprite {
+            sprite.color = Color::srgba(1.0, 1.0 - progress, 1.0 - progress, 1.0 - progress);
+        }
+    }
+    if !player_query.is_empty()
+        && player_query
+            .iter()
+            .all(|(_, dying)| dying.is_some_and(|dying| dying.finished()))
+    {
+        run_stats.score = player_query.iter().map(|(player, _)| player.score).sum();
+        if playback.is_some() {
+            // Watching a replay doesn't set a new record
+            high_scores.last_rank = None;
+        } else {
+            let best_player = player_query
+                .iter()
+                .map(|(player, _)| player)
+                .max_by_key(|player| player.score);
+            high_scores.record(
+                HighScore {
+                    score: run_stats.score,
+                    kills: run_stats.kills,
+                    time_survived: run_stats.time_survived,
+                    weapon: best_player.map(|player| player.form).unwrap_or_default(),
+                    seed: run_seed.seed,
+                    daily: run_seed.daily.clone(),
+                    date: today(),
+                },
+                &storage,
+            );
+        }
+        next_state.set(GameState::GameOver);
+    }
+}
+
+fn setup_game_over(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    run_stats: Res<RunStats>,
+    run_seed: Res<RunSeed>,
+    high_scores: Res<HighScores>,
+) {
+    let seconds = run_stats.time_survived as u32;
+    let text_font = |font_size| TextFont {
+        font: fonts.font.clone(),
+        font_size,
+        ..default()
+    };
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(Color::linear_rgb(0.05, 0.0, 0.0)),
+            StateScoped(GameState::GameOver),
+        ))
+        .with_children(|children| {
+            children.spawn((
+                Text::new("Game Over"),
+                text_font(64.0),
+                TextColor(Color::linear_rgb(0.8, 0.1, 0.1)),
+            ));
+            for line in [
+                format!("Score: {}", run_stats.score),
+                format!("Kills: {}", run_stats.kills),
+                format!("Time survived: {}:{:02}", seconds / 60, seconds % 60),
+                match &run_seed.daily {
+                    Some(date) => format!("Daily challenge {date}"),
+                    None => format!("Seed: {}", run_seed.seed),
+                },
+            ] {
+                children.spawn((Text::new(line), text_font(32.0)));
+            }
+            if high_scores.new_record() {
+                children.spawn((
+                    Text::new("New record!"),
+                    text_font(40.0),
+                    TextColor(Color::srgb(1.0, 0.85, 0.1)),
+                ));
+            }
+            spawn_high_score_table(
+                children,
+                &high_scores,
+                &fonts.font,
+                MAX_HIGH_SCORES,
+                high_scores.last_rank,
+            );
+            spawn_button(children, "Restart", ChangeState(GameState::Playing));
+            spawn_button(children, "Menu", ChangeState(GameState::Menu));
+        });
+}
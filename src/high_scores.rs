@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::player::PlayerForm;
+use crate::storage::Storage;
+
+/// Only the best runs are kept
+pub const MAX_HIGH_SCORES: usize = 10;
+
+const HIGHLIGHT_COLOR: Color = Color::srgb(1.0, 0.85, 0.1);
+
+/// The best runs on this device, best first
+#[derive(Resource, Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct HighScores {
+    pub entries: Vec<HighScore>,
+    /// Where the last finished run got in, if at all
+    #[serde(skip)]
+    pub last_rank: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HighScore {
+    pub score: u32,
+    pub kills: u32,
+    pub time_survived: f32,
+    pub weapon: PlayerForm,
+    pub seed: u64,
+    /// The date, if the run was a daily challenge
+    pub daily: Option<String>,
+    /// When the run was played, `YYYY-MM-DD`
+    pub date: String,
+}
+
+impl HighScores {
+    pub fn load(storage: &Storage) -> Self {
+        storage.load("high_scores").unwrap_or_default()
+    }
+
+    /// Adds the run if it's good enough and stores the table
+    pub fn record(&mut self, high_score: HighScore, storage: &Storage) {
+        // Later runs rank below earlier ones with the same score
+        let rank = self
+            .entries
+            .iter()
+            .position(|entry| entry.score < high_score.score)
+            .unwrap_or(self.entries.len());
+        if rank >= MAX_HIGH_SCORES {
+            self.last_rank = None;
+            return;
+        }
+        self.entries.insert(rank, high_score);
+        self.entries.truncate(MAX_HIGH_SCORES);
+        self.last_rank = Some(rank);
+        storage.save("high_scores", self);
+    }
+
+    /// The last finished run is the best one so far
+    pub fn new_record(&self) -> bool {
+        self.last_rank == Some(0)
+    }
+}
+
+/// Spawns the best `rows` runs as a table, `highlight` is the rank to highlight
+pub fn spawn_high_score_table(
+    parent: &mut ChildBuilder,
+    high_scores: &HighScores,
+    font: &Handle<Font>,
+    rows: usize,
+    highlight: Option<usize>,
+) {
+    if high_scores.entries.is_empty() {
+        return;
+    }
+    let text_font = TextFont {
+        font: font.clone(),
+        font_size: 20.0,
+        ..default()
+    };
+    parent
+        .spawn(Node {
+            display: Display::Grid,
+            grid_template_columns: vec![GridTrack::auto(); 7],
+            column_gap: Val::Px(16.0),
+            margin: UiRect::all(Val::Px(10.0)),
+            ..default()
+        })
+        .with_children(|table| {
+            let headers = ["#", "Score", "Kills", "Time", "Weapon", "Seed", "Date"];
+            for header in headers {
+                table.spawn((
+                    Text::new(header),
+                    text_font.clone(),
+                    TextColor(Color::srgb(0.6, 0.6, 0.6)),
+                ));
+            }
+            for (rank, entry) in high_scores.entries.iter().take(rows).enumerate() {
+                let seconds = entry.time_survived as u32;
+                let color = if highlight == Some(rank) {
+                    HIGHLIGHT_COLOR
+                } else {
+                    Color::WHITE
+                };
+                for cell in [
+                    format!("{}", rank + 1),
+                    entry.score.to_string(),
+                    entry.kills.to_string(),
+                    format!("{}:{:02}", seconds / 60, seconds % 60),
+                    format!("{:?}", entry.weapon),
+                    match &entry.daily {
+                        Some(_) => "Daily".to_string(),
+                        None => entry.seed.to_string(),
+                    },
+                    entry.date.clone(),
+                ] {
+                    table.spawn((Text::new(cell), text_font.clone(), TextColor(color)));
+                }
+            }
+        });
+}
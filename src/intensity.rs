@@ -0,0 +1,83 @@
+//! How intense the fight is, from 0 when nothing's going on to 1 in the thick of it.
+//! The music switches to the combat playlist when it gets high, see `crate::audio`.
+
+use bevy::prelude::*;
+
+use crate::actions::{DamageEvent, Health};
+use crate::enemies::Ai;
+use crate::player::Player;
+use crate::GameState;
+
+/// Enemies closer than this to a player count as threats
+const THREAT_RADIUS: f32 = 120.0;
+/// This many threats alone make for full intensity
+const FULL_THREATS: f32 = 8.0;
+/// Added by every point of damage dealt or taken
+const HEAT_PER_DAMAGE: f32 = 0.15;
+/// Lost per second
+const HEAT_DECAY: f32 = 0.2;
+/// How fast the intensity follows what's going on
+const FOLLOW_SPEED: f32 = 1.5;
+
+pub struct IntensityPlugin;
+
+/// This plugin rates how intense the fight is for the music
+impl Plugin for IntensityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CombatIntensity>()
+            .add_systems(OnEnter(GameState::Playing), reset_intensity)
+            .add_systems(OnExit(GameState::Playing), reset_intensity)
+            .add_systems(
+                Update,
+                update_intensity.run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct CombatIntensity {
+    pub value: f32,
+    /// A player alive is down to the last heart
+    pub low_health: bool,
+    /// Recent damage, dealt and taken
+    heat: f32,
+}
+
+fn reset_intensity(mut intensity: ResMut<CombatIntensity>) {
+    *intensity = default();
+}
+
+/// Made up of the threats around the players, recent damage and how hurt the players are
+fn update_intensity(
+    time: Res<Time>,
+    mut damage_events: EventReader<DamageEvent>,
+    player_query: Query<(&Transform, &Health), With<Player>>,
+    threat_query: Query<&Transform, (With<Ai>, With<Health>)>,
+    mut intensity: ResMut<CombatIntensity>,
+) {
+    let delta = time.delta_secs();
+    let damage: u32 = damage_events.read().map(|event| event.amount).sum();
+    intensity.heat =
+        (intensity.heat + damage as f32 * HEAT_PER_DAMAGE - HEAT_DECAY * delta).clamp(0.0, 1.0);
+
+    let threats = threat_query
+        .iter()
+        .filter(|threat| {
+            player_query.iter().any(|(player, _)| {
+                player.translation.xy().distance(threat.translation.xy()) < THREAT_RADIUS
+            })
+        })
+        .count();
+    // Of the most hurt player
+    let hurt = player_query
+        .iter()
+        .map(|(_, health)| 1.0 - health.health as f32 / health.max_health.max(1) as f32)
+        .reduce(f32::max)
+        .unwrap_or(0.0);
+    intensity.low_health = player_query.iter().any(|(_, health)| health.health <= 1);
+
+    let target =
+        (0.5 * (threats as f32 / FULL_THREATS).min(1.0) + 0.35 * intensity.heat + 0.15 * hurt)
+            .min(1.0);
+    intensity.value += (target - intensity.value) * (1.0 - (-FOLLOW_SPEED * delta).exp());
+}
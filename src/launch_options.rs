@@ -0,0 +1,48 @@
+use bevy::prelude::*;
+use std::path::PathBuf;
+
+use crate::seed::{SeedOption, SEED_ENV_VAR};
+
+/// Options given on the command line. Platforms without one just use the defaults.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct LaunchOptions {
+    /// Write the replay of every run to this file
+    pub record: Option<PathBuf>,
+    /// Play this replay instead of showing the menu
+    pub replay: Option<PathBuf>,
+    /// Seed of every run, instead of a random one
+    pub seed: Option<SeedOption>,
+    /// Name of the asset pack in `assets/packs` that replaces some of the assets
+    pub asset_pack: Option<String>,
+}
+
+impl LaunchOptions {
+    /// Parses the arguments, without the program name
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => options.record = args.next().map(PathBuf::from),
+                "--replay" => options.replay = args.next().map(PathBuf::from),
+                "--seed" => options.seed = args.next().as_deref().map(SeedOption::parse),
+                "--daily" => options.seed = Some(SeedOption::Daily),
+                "--asset-pack" => options.asset_pack = args.next(),
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        options
+    }
+
+    /// The arguments of this process, with the seed from the environment unless one was given
+    pub fn from_env() -> Self {
+        let mut options = Self::from_args(std::env::args().skip(1));
+        if options.seed.is_none() {
+            options.seed = std::env::var(SEED_ENV_VAR)
+                .ok()
+                .as_deref()
+                .map(SeedOption::parse);
+        }
+        options
+    }
+}
@@ -3,31 +3,62 @@
 mod actions;
 mod audio;
 mod camera;
+mod controls_menu;
+mod damage_feedback;
 mod enemies;
+mod game_over;
+mod high_scores;
+mod intensity;
+mod launch_options;
 mod loading;
 mod menu;
+mod pause;
 mod physics;
+mod pickups;
 mod player;
+mod progression;
+mod replay;
+mod seed;
+mod settings;
+mod storage;
+#[cfg(test)]
+mod tests;
 mod tiled;
 mod tilemap;
+mod touch_controls;
 mod ui;
 
 use crate::actions::ActionsPlugin;
-use crate::audio::InternalAudioPlugin;
+use crate::audio::{InternalAudioPlugin, SilentAudioPlugin};
 use crate::camera::CameraPlugin;
+use crate::controls_menu::ControlsMenuPlugin;
+use crate::damage_feedback::DamageFeedbackPlugin;
 use crate::enemies::EnemiesPlugin;
-use crate::loading::LoadingPlugin;
+use crate::game_over::GameOverPlugin;
+use crate::intensity::IntensityPlugin;
+use crate::loading::{LoadingPlugin, PlaceholderAssetsPlugin};
 use crate::menu::MenuPlugin;
+use crate::pause::PausePlugin;
+use crate::pickups::PickupsPlugin;
 use crate::player::PlayerPlugin;
+use crate::progression::ProgressionPlugin;
+use crate::replay::ReplayPlugin;
+use crate::settings::SettingsPlugin;
+use crate::storage::Storage;
 use crate::tiled::TiledMapPlugin;
 use crate::tilemap::TilemapPlugin;
+use crate::touch_controls::TouchControlsPlugin;
 use crate::ui::UiPlugin;
 
-use avian2d::{prelude::Gravity, PhysicsPlugins};
+use avian2d::prelude::{Gravity, PhysicsInterpolationPlugin};
+use avian2d::PhysicsPlugins;
 use bevy_aseprite_ultra::AsepriteUltraPlugin;
 use bevy_enoki::EnokiPlugin;
 use bevy_rand::prelude::{EntropyPlugin, WyRand};
 
+pub use crate::launch_options::LaunchOptions;
+pub use crate::seed::SeedOption;
+
 use bevy::app::App;
 #[cfg(debug_assertions)]
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
@@ -45,35 +76,87 @@ enum GameState {
     Playing,
     // Here the menu is drawn and waiting for player interaction
     Menu,
+    // The player died, the run summary is shown
+    GameOver,
+    // An asset could not be loaded, the errors are shown
+    LoadingFailed,
+}
+
+// Gameplay systems should only run while the game is not paused
+#[derive(SubStates, Default, Clone, Copy, Eq, PartialEq, Debug, Hash)]
+#[source(GameState = GameState::Playing)]
+enum PauseState {
+    #[default]
+    Running,
+    Paused,
+    // A player levelled up and picks an upgrade
+    LevelUp,
 }
 
-pub struct GamePlugin;
+#[derive(Default)]
+pub struct GamePlugin {
+    /// Runs without window, audio device or GPU, e.g. for tests.
+    /// Assets are not loaded, the game starts in the menu with placeholder handles
+    /// and nothing is read from or written to storage.
+    pub headless: bool,
+}
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        app.init_state::<GameState>()
-            .add_plugins((
+        // The plugins below load their data while they are built
+        app.insert_resource(if self.headless {
+            Storage::Disabled
+        } else {
+            Storage::Persistent
+        });
+        if self.headless {
+            app.insert_state(GameState::Menu)
+                .add_plugins((PlaceholderAssetsPlugin, SilentAudioPlugin));
+        } else {
+            app.init_state::<GameState>().add_plugins((
                 LoadingPlugin,
-                MenuPlugin,
-                ActionsPlugin,
                 InternalAudioPlugin,
-                PlayerPlugin,
                 TilemapPlugin,
                 TiledMapPlugin,
-                EnemiesPlugin,
-                PhysicsPlugins::default(),
                 EnokiPlugin,
                 AsepriteUltraPlugin,
+            ));
+
+            #[cfg(debug_assertions)]
+            {
+                app.add_plugins((FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin::default()));
+            }
+        }
+
+        app.init_resource::<LaunchOptions>()
+            .add_sub_state::<PauseState>()
+            .add_plugins((
+                MenuPlugin,
+                ActionsPlugin,
+                PlayerPlugin,
+                EnemiesPlugin,
                 CameraPlugin,
                 UiPlugin,
+                DamageFeedbackPlugin,
+                GameOverPlugin,
+            ))
+            .add_plugins((
+                PausePlugin,
+                SettingsPlugin,
+                ControlsMenuPlugin,
+                TouchControlsPlugin,
+                ReplayPlugin,
+                ProgressionPlugin,
+                PickupsPlugin,
+                IntensityPlugin,
+            ))
+            .add_plugins((
+                // Gameplay and physics run in `FixedUpdate`, rendering shows the bodies in between
+                PhysicsPlugins::default().set(PhysicsInterpolationPlugin::interpolate_all()),
                 EntropyPlugin::<WyRand>::default(),
             ))
             .enable_state_scoped_entities::<GameState>()
+            .enable_state_scoped_entities::<PauseState>()
             .insert_resource(Gravity(Vec2::ZERO));
-
-        #[cfg(debug_assertions)]
-        {
-            app.add_plugins((FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin::default()));
-        }
     }
 }
@@ -1,52 +1,338 @@
+use crate::audio::Playlists;
+use crate::pickups::DropTables;
 use crate::tiled::TiledMap;
+use bevy::asset::{LoadedUntypedAsset, UntypedAssetLoadFailedEvent};
 use bevy::prelude::*;
 use bevy_aseprite_ultra::prelude::*;
 use bevy_asset_loader::prelude::*;
 use bevy_enoki::Particle2dEffect;
 use bevy_kira_audio::AudioSource;
+use iyes_progress::prelude::*;
 
-use crate::GameState;
+use crate::{GameState, LaunchOptions};
+
+/// Every asset collection gets its paths from this manifest
+const ASSET_MANIFEST: &str = "default.assets.ron";
+/// Of the title image in the manifest, the loading screen shows it as soon as it's known
+const TITLE_KEY: &str = "textures.title";
 
 pub struct LoadingPlugin;
 
 /// This plugin loads all assets using [`AssetLoader`] from a third party bevy plugin
 /// Alternatively you can write the logic to load assets yourself
 /// If interested, take a look at <https://bevy-cheatbook.github.io/features/assets.html>
+/// The paths are in [`ASSET_MANIFEST`], the asset pack of the [`LaunchOptions`] overrides some.
+/// While loading, a progress bar is shown. If anything fails to load, the game doesn't start and
+/// the assets that failed are listed instead.
 impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_loading_state(
-            LoadingState::new(GameState::Loading)
-                .continue_to_state(GameState::Menu)
+        let mut loading_state = LoadingState::new(GameState::Loading)
+            .on_failure_continue_to_state(GameState::LoadingFailed)
+            .with_dynamic_assets_file::<StandardDynamicAssetCollection>(ASSET_MANIFEST);
+        // The launch options are inserted before the game plugin is added, if at all
+        let asset_pack = app
+            .world()
+            .get_resource::<LaunchOptions>()
+            .and_then(|options| options.asset_pack.clone());
+        if let Some(asset_pack) = asset_pack {
+            info!("Using asset pack {asset_pack}");
+            loading_state = loading_state
+                .with_dynamic_assets_file::<StandardDynamicAssetCollection>(&format!(
+                    "packs/{asset_pack}.assets.ron"
+                ));
+        }
+
+        app.add_plugins(
+            ProgressPlugin::<GameState>::new()
+                .with_state_transition(GameState::Loading, GameState::Menu),
+        )
+        .init_resource::<LoadingLog>()
+        .add_systems(OnEnter(GameState::Loading), setup_loading_screen)
+        .add_systems(OnEnter(GameState::LoadingFailed), setup_error_screen)
+        .add_systems(
+            Update,
+            (
+                (
+                    record_loaded::<Image>,
+                    record_loaded::<Aseprite>,
+                    record_loaded::<AudioSource>,
+                    record_loaded::<Particle2dEffect>,
+                    record_loaded::<TiledMap>,
+                    record_loaded::<DropTables>,
+                    record_loaded::<Playlists>,
+                    record_loaded::<Font>,
+                    show_title,
+                    update_loading_screen,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Loading)),
+                (record_failed, update_error_screen)
+                    .chain()
+                    .run_if(in_state(GameState::Loading).or(in_state(GameState::LoadingFailed))),
+            ),
+        )
+        .add_loading_state(
+            loading_state
                 .load_collection::<AudioAssets>()
                 .load_collection::<TextureAssets>()
                 .load_collection::<RangedEnemyAssets>()
                 .load_collection::<EffectAssets>()
                 .load_collection::<TileMapAssets>()
+                .load_collection::<DropTableAssets>()
+                .load_collection::<MusicAssets>()
                 .load_collection::<Fonts>(),
         );
     }
 }
 
+/// Inserts every asset collection with empty handles instead of loading them.
+/// Headless runs use this, nothing there looks at the assets.
+pub struct PlaceholderAssetsPlugin;
+
+impl Plugin for PlaceholderAssetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AudioAssets>()
+            .init_resource::<TextureAssets>()
+            .init_resource::<RangedEnemyAssets>()
+            .init_resource::<EffectAssets>()
+            .init_resource::<TileMapAssets>()
+            .init_resource::<DropTableAssets>()
+            .init_resource::<MusicAssets>()
+            .init_resource::<Fonts>();
+    }
+}
+
+/// What happened while loading, for the loading and error screens
+#[derive(Resource, Default)]
+struct LoadingLog {
+    /// Path of the asset loaded last
+    last_loaded: Option<String>,
+    /// Path and error of every asset that failed to load
+    failed: Vec<(String, String)>,
+}
+
+/// Shows the title once it's loaded
+#[derive(Component, Default)]
+struct LoadingTitle(Option<UntypedHandle>);
+
+#[derive(Component)]
+struct ProgressBar;
+
+#[derive(Component)]
+struct LoadedAssetText;
+
+#[derive(Component)]
+struct LoadingErrorList;
+
+/// The title and a progress bar. The fonts aren't loaded yet, so it uses the default font.
+fn setup_loading_screen(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::End,
+                padding: UiRect::bottom(Val::Px(40.0)),
+                ..default()
+            },
+            LoadingTitle::default(),
+            StateScoped(GameState::Loading),
+        ))
+        .with_children(|children| {
+            children
+                .spawn((
+                    Node {
+                        width: Val::Px(400.0),
+                        height: Val::Px(16.0),
+                        ..default()
+                    },
+                    BackgroundColor(Color::linear_rgb(0.15, 0.15, 0.15)),
+                ))
+                .with_child((
+                    Node {
+                        width: Val::Percent(0.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    BackgroundColor(Color::linear_rgb(0.9, 0.9, 0.9)),
+                    ProgressBar,
+                ));
+            children.spawn((
+                Text::new("Loading"),
+                TextFont {
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(Color::linear_rgb(0.9, 0.9, 0.9)),
+                LoadedAssetText,
+            ));
+        });
+}
+
+/// The manifest and asset pack are read first, then the title is loaded from where they say
+fn show_title(
+    mut commands: Commands,
+    dynamic_assets: Res<DynamicAssets>,
+    asset_server: Res<AssetServer>,
+    loaded_assets: Res<Assets<LoadedUntypedAsset>>,
+    mut title_query: Query<(Entity, &mut LoadingTitle)>,
+) {
+    for (entity, mut title) in title_query.iter_mut() {
+        if title.0.is_none() {
+            title.0 = dynamic_assets
+                .get_asset(TITLE_KEY)
+                .and_then(|asset| asset.load(&asset_server).into_iter().next());
+        }
+        let Some(handle) = title.0.clone() else {
+            continue;
+        };
+        // Files are loaded untyped, what they turned out to be is known once they're loaded
+        let image = match handle.clone().try_typed::<LoadedUntypedAsset>() {
+            Ok(file) => match loaded_assets.get(&file) {
+                Some(loaded) => loaded.handle.clone(),
+                None => continue,
+            },
+            Err(_) => handle,
+        };
+        let mut entity = commands.entity(entity);
+        entity.remove::<LoadingTitle>();
+        match image.try_typed::<Image>() {
+            Ok(image) => {
+                entity.insert(ImageNode { image, ..default() });
+            }
+            Err(e) => error!("The title is not an image: {e}"),
+        }
+    }
+}
+
+fn record_loaded<A: Asset>(
+    mut asset_events: EventReader<AssetEvent<A>>,
+    asset_server: Res<AssetServer>,
+    mut log: ResMut<LoadingLog>,
+) {
+    for event in asset_events.read() {
+        if let AssetEvent::LoadedWithDependencies { id } = event {
+            if let Some(path) = asset_server.get_path(*id) {
+                log.last_loaded = Some(path.to_string());
+            }
+        }
+    }
+}
+
+fn record_failed(
+    mut failed_events: EventReader<UntypedAssetLoadFailedEvent>,
+    mut log: ResMut<LoadingLog>,
+) {
+    for event in failed_events.read() {
+        error!("Failed to load {}: {}", event.path, event.error);
+        log.failed
+            .push((event.path.to_string(), event.error.to_string()));
+    }
+}
+
+fn update_loading_screen(
+    progress: Res<ProgressTracker<GameState>>,
+    log: Res<LoadingLog>,
+    mut bar_query: Query<&mut Node, With<ProgressBar>>,
+    mut text_query: Query<&mut Text, With<LoadedAssetText>>,
+) {
+    let progress: f32 = progress.get_global_progress().into();
+    for mut node in bar_query.iter_mut() {
+        node.width = Val::Percent(progress * 100.0);
+    }
+    if let Some(last_loaded) = log.last_loaded.as_ref().filter(|_| log.is_changed()) {
+        for mut text in text_query.iter_mut() {
+            text.0 = format!("Loaded {last_loaded}");
+        }
+    }
+}
+
+fn setup_error_screen(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: Val::Px(16.0),
+                padding: UiRect::all(Val::Px(20.0)),
+                ..default()
+            },
+            StateScoped(GameState::LoadingFailed),
+        ))
+        .with_children(|children| {
+            children.spawn((
+                Text::new("The game could not be loaded"),
+                TextFont {
+                    font_size: 40.0,
+                    ..default()
+                },
+            ));
+            children.spawn((
+                Text::new("Make sure the assets folder is complete and try again."),
+                TextFont {
+                    font_size: 20.0,
+                    ..default()
+                },
+            ));
+            children.spawn((
+                Text::default(),
+                TextFont {
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(Color::linear_rgb(1.0, 0.4, 0.4)),
+                LoadingErrorList,
+            ));
+        });
+}
+
+/// Further errors can come in after the error screen is shown
+fn update_error_screen(
+    log: Res<LoadingLog>,
+    mut text_query: Query<(&mut Text, Ref<LoadingErrorList>)>,
+) {
+    for (mut text, list) in text_query.iter_mut() {
+        if !log.is_changed() && !list.is_added() {
+            continue;
+        }
+        let errors: Vec<String> = log
+            .failed
+            .iter()
+            .map(|(path, error)| format!("{path}: {error}"))
+            .collect();
+        text.0 = errors.join("\n");
+    }
+}
+
 // the following asset collections will be loaded during the State `GameState::Loading`
 // when done loading, they will be inserted as resources (see <https://github.com/NiklasEi/bevy_asset_loader>)
 
-#[derive(AssetCollection, Resource)]
+#[derive(AssetCollection, Resource, Default)]
 pub struct AudioAssets {
-    #[asset(path = "audio/woosh.ogg")]
+    #[asset(key = "audio.woosh")]
     pub woosh: Handle<AudioSource>,
-    #[asset(path = "audio/Melee_Enemy_Attack.mp3")]
+    #[asset(key = "audio.enemy_1_attack")]
     pub enemy_1_attack: Handle<AudioSource>,
-    #[asset(path = "audio/Player_Damaged_Effected.mp3")]
+    #[asset(key = "audio.player_damaged_effected")]
     pub player_damaged_effected: Handle<AudioSource>,
+    #[asset(key = "audio.heartbeat")]
+    pub heartbeat: Handle<AudioSource>,
 }
 
-#[derive(AssetCollection, Resource)]
+#[derive(AssetCollection, Resource, Default)]
 pub struct TextureAssets {
-    #[asset(path = "textures/arrow.png")]
+    #[asset(key = "textures.arrow")]
     pub arrow: Handle<Image>,
-    #[asset(path = "textures/player_sword.aseprite")]
+    #[asset(key = "textures.player_arrow")]
+    pub player_arrow: Handle<Image>,
+    #[asset(key = "textures.player_sword")]
     pub player_sword: Handle<Aseprite>,
-    #[asset(path = "textures/player_Bow.aseprite")]
+    #[asset(key = "textures.player_bow")]
     pub player_bow: Handle<Aseprite>,
     //#[asset(path = "textures/Player_Mace_1.png")]
     //pub player_mace: Handle<Image>,
@@ -54,60 +340,72 @@ pub struct TextureAssets {
     //pub player_spear: Handle<Image>,
     //#[asset(path = "textures/player_Bow.png")]
     //pub player_bow: Handle<Image>,
-    #[asset(path = "textures/Enemy_Melee_1_Walk_Left.aseprite")]
+    #[asset(key = "textures.enemy_1_left")]
     pub enemy_1_left: Handle<Aseprite>,
-    #[asset(path = "textures/Enemy_Melee_1_Walk_Right.aseprite")]
+    #[asset(key = "textures.enemy_1_right")]
     pub enemy_1_right: Handle<Aseprite>,
-    #[asset(path = "textures/Enemy_Melee_1_Attack_Left.aseprite")]
+    #[asset(key = "textures.enemy_1_attack_left")]
     pub enemy_1_attack_left: Handle<Aseprite>,
-    #[asset(path = "textures/Enemy_Melee_1_Death_Left.aseprite")]
+    #[asset(key = "textures.enemy_1_death")]
     pub enemy_1_death: Handle<Aseprite>,
-    #[asset(path = "textures/Enemy_Melee_1_Attack_Right.aseprite")]
+    #[asset(key = "textures.enemy_1_attack_right")]
     pub enemy_1_attack_right: Handle<Aseprite>,
     //#[asset(path = "textures/Melee_Enemy_2.aseprite")]
     //pub enemy_2: Handle<Aseprite>,
     //#[asset(path = "textures/Melee_Enemy_3.aseprite")]
     //pub enemy_3: Handle<Aseprite>,
-    #[asset(path = "textures/Player_Life.aseprite")]
+    #[asset(key = "textures.player_life")]
     pub player_life: Handle<Aseprite>,
-    #[asset(path = "textures/title.png")]
+    #[asset(key = "textures.title")]
     pub title: Handle<Image>,
 }
 
-#[derive(AssetCollection, Resource)]
+#[derive(AssetCollection, Resource, Default)]
 pub struct RangedEnemyAssets {
-    #[asset(path = "textures/Enemy_Ranged_1_Walk_Left.aseprite")]
+    #[asset(key = "ranged_enemy.walk_left")]
     pub walk_left: Handle<Aseprite>,
-    #[asset(path = "textures/Enemy_Ranged_1_Walk_Right.aseprite")]
+    #[asset(key = "ranged_enemy.walk_right")]
     pub walk_right: Handle<Aseprite>,
-    #[asset(path = "textures/Enemy_Ranged_1_Attack_Left.aseprite")]
+    #[asset(key = "ranged_enemy.attack_left")]
     pub attack_left: Handle<Aseprite>,
-    #[asset(path = "textures/Enemy_Ranged_1_Attack_Right.aseprite")]
+    #[asset(key = "ranged_enemy.attack_right")]
     pub attack_right: Handle<Aseprite>,
-    #[asset(path = "textures/Enemy_Ranged_1_Death_Right.aseprite")]
+    #[asset(key = "ranged_enemy.death")]
     pub death: Handle<Aseprite>,
-    #[asset(path = "textures/Ranged_Enemy_Projectile.aseprite")]
+    #[asset(key = "ranged_enemy.projectile")]
     pub projectile: Handle<Aseprite>,
-    #[asset(path = "audio/Ranged_Enemy_Attack.mp3")]
+    #[asset(key = "ranged_enemy.attack_sound")]
     pub attack_sound: Handle<AudioSource>,
 }
 
-#[derive(AssetCollection, Resource)]
+#[derive(AssetCollection, Resource, Default)]
 pub struct TileMapAssets {
-    #[asset(path = "level1.tmx")]
+    #[asset(key = "maps.level_1")]
     pub level_1: Handle<TiledMap>,
 }
 
-#[derive(AssetCollection, Resource)]
+#[derive(AssetCollection, Resource, Default)]
+pub struct DropTableAssets {
+    #[asset(key = "drops.enemies")]
+    pub enemies: Handle<DropTables>,
+}
+
+#[derive(AssetCollection, Resource, Default)]
+pub struct MusicAssets {
+    #[asset(key = "music.playlists")]
+    pub playlists: Handle<Playlists>,
+}
+
+#[derive(AssetCollection, Resource, Default)]
 pub struct EffectAssets {
-    #[asset(path = "effects/sword-slash.ron")]
+    #[asset(key = "effects.sword_slash")]
     pub sword_slash: Handle<Particle2dEffect>,
-    #[asset(path = "effects/enemy_1-attack.ron")]
+    #[asset(key = "effects.enemy_1_attack")]
     pub enemy_1_attack: Handle<Particle2dEffect>,
 }
 
-#[derive(AssetCollection, Resource)]
+#[derive(AssetCollection, Resource, Default)]
 pub struct Fonts {
-    #[asset(path = "Pixelated Elegance.ttf")]
+    #[asset(key = "fonts.font")]
     pub font: Handle<Font>,
 }
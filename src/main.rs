@@ -6,13 +6,14 @@ use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use bevy::winit::WinitWindows;
 use bevy::DefaultPlugins;
-use blade_soul::GamePlugin;
+use blade_soul::{GamePlugin, LaunchOptions};
 use std::io::Cursor;
 use winit::window::Icon;
 
 fn main() {
     App::new()
         .insert_resource(ClearColor(Color::linear_rgb(0.4, 0.4, 0.4)))
+        .insert_resource(LaunchOptions::from_env())
         .add_plugins(
             DefaultPlugins
                 .set(WindowPlugin {
@@ -33,7 +34,7 @@ fn main() {
                 })
                 .set(ImagePlugin::default_nearest()),
         )
-        .add_plugins(GamePlugin)
+        .add_plugins(GamePlugin::default())
         .add_systems(Startup, set_window_icon)
         .run();
 }
@@ -1,6 +1,12 @@
 use bevy::prelude::*;
+use bevy::state::state::FreelyMutableState;
 
-use crate::loading::TextureAssets;
+use crate::actions::{ActionState, GameControl};
+use crate::audio::{PlaySfx, Sound};
+use crate::high_scores::{spawn_high_score_table, HighScores};
+use crate::loading::{Fonts, TextureAssets};
+use crate::replay::{LastRun, WatchLastRun};
+use crate::settings::SettingsState;
 use crate::GameState;
 
 pub struct MenuPlugin;
@@ -10,14 +16,24 @@ pub struct MenuPlugin;
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::Menu), setup_menu)
-            .add_systems(Update, click_play_button.run_if(in_state(GameState::Menu)));
+            .add_systems(
+                Update,
+                (
+                    start_with_attack
+                        .run_if(in_state(GameState::Menu))
+                        .run_if(in_state(SettingsState::Closed)),
+                    update_button_colors,
+                    click_change_state::<GameState>,
+                    click_open_link,
+                ),
+            );
     }
 }
 
 #[derive(Component)]
-struct ButtonColors {
-    normal: Color,
-    hovered: Color,
+pub struct ButtonColors {
+    pub normal: Color,
+    pub hovered: Color,
 }
 
 impl Default for ButtonColors {
@@ -29,7 +45,16 @@ impl Default for ButtonColors {
     }
 }
 
-fn setup_menu(textures: Res<TextureAssets>, mut commands: Commands) {
+/// The best runs shown below the buttons
+const MENU_HIGH_SCORES: usize = 5;
+
+fn setup_menu(
+    textures: Res<TextureAssets>,
+    fonts: Res<Fonts>,
+    last_run: Res<LastRun>,
+    high_scores: Res<HighScores>,
+    mut commands: Commands,
+) {
     info!("menu");
     commands
         .spawn((
@@ -48,68 +73,68 @@ fn setup_menu(textures: Res<TextureAssets>, mut commands: Commands) {
             StateScoped(GameState::Menu),
         ))
         .with_children(|children| {
-            let button_colors = ButtonColors::default();
-            children
-                .spawn((
-                    Button,
-                    Node {
-                        width: Val::Px(140.0),
-                        height: Val::Px(50.0),
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::Center,
-                        ..Default::default()
-                    },
-                    BackgroundColor(button_colors.normal),
-                    button_colors,
-                    ChangeState(GameState::Playing),
-                ))
-                .with_child((
-                    Text::new("Play"),
-                    TextFont {
-                        font_size: 40.0,
-                        ..default()
-                    },
-                    TextColor(Color::linear_rgb(0.9, 0.9, 0.9)),
-                ));
+            spawn_button(children, "Play", ChangeState(GameState::Playing));
+            if last_run.0.is_some() {
+                spawn_button(children, "Replay", WatchLastRun);
+            }
+            spawn_button(children, "Settings", ChangeState(SettingsState::Open));
+            spawn_high_score_table(children, &high_scores, &fonts.font, MENU_HIGH_SCORES, None);
         });
 }
 
+/// Spawns a menu button labeled `label`. `action` decides what happens when it is pressed,
+/// i.e. [`ChangeState`] or [`OpenLink`].
+pub fn spawn_button(parent: &mut ChildBuilder, label: &str, action: impl Bundle) {
+    let button_colors = ButtonColors::default();
+    parent
+        .spawn((
+            Button,
+            Node {
+                width: Val::Px(140.0),
+                height: Val::Px(50.0),
+                margin: UiRect::all(Val::Px(5.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            BackgroundColor(button_colors.normal),
+            button_colors,
+            action,
+        ))
+        .with_child((
+            Text::new(label),
+            TextFont {
+                font_size: 40.0,
+                ..default()
+            },
+            TextColor(Color::linear_rgb(0.9, 0.9, 0.9)),
+        ));
+}
+
+/// Pressing a button with this component switches to the given state
 #[derive(Component)]
-struct ChangeState(GameState);
+pub struct ChangeState<S: FreelyMutableState>(pub S);
 
 #[derive(Component)]
-struct OpenLink(&'static str);
+pub struct OpenLink(pub &'static str);
+
+fn start_with_attack(mut next_state: ResMut<NextState<GameState>>, action_state: Res<ActionState>) {
+    if action_state.just_pressed(GameControl::Attack) {
+        next_state.set(GameState::Playing);
+    }
+}
 
-fn click_play_button(
-    mut next_state: ResMut<NextState<GameState>>,
+fn update_button_colors(
     mut interaction_query: Query<
-        (
-            &Interaction,
-            &mut BackgroundColor,
-            &ButtonColors,
-            Option<&ChangeState>,
-            Option<&OpenLink>,
-        ),
+        (&Interaction, &mut BackgroundColor, &ButtonColors),
         (Changed<Interaction>, With<Button>),
     >,
-    gamepad_input: Query<&Gamepad>,
+    mut sfx_events: EventWriter<PlaySfx>,
 ) {
-    if gamepad_input
-        .iter()
-        .any(|g| g.pressed(GamepadButton::South))
-    {
-        next_state.set(GameState::Playing);
-    }
-    for (interaction, mut color, button_colors, change_state, open_link) in &mut interaction_query {
+    for (interaction, mut color, button_colors) in &mut interaction_query {
         match *interaction {
             Interaction::Pressed => {
-                if let Some(state) = change_state {
-                    next_state.set(state.0.clone());
-                } else if let Some(link) = open_link {
-                    if let Err(error) = webbrowser::open(link.0) {
-                        warn!("Failed to open link {error:?}");
-                    }
-                }
+                sfx_events.send(PlaySfx::new(Sound::Click));
             }
             Interaction::Hovered => {
                 *color = button_colors.hovered.into();
@@ -120,3 +145,26 @@ fn click_play_button(
         }
     }
 }
+
+pub fn click_change_state<S: FreelyMutableState>(
+    mut next_state: ResMut<NextState<S>>,
+    interaction_query: Query<(&Interaction, &ChangeState<S>), (Changed<Interaction>, With<Button>)>,
+) {
+    for (interaction, change_state) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            next_state.set(change_state.0.clone());
+        }
+    }
+}
+
+fn click_open_link(
+    interaction_query: Query<(&Interaction, &OpenLink), (Changed<Interaction>, With<Button>)>,
+) {
+    for (interaction, link) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            if let Err(error) = webbrowser::open(link.0) {
+                warn!("Failed to open link {error:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use bevy::window::AppLifecycle;
+use bevy_kira_audio::prelude::*;
+
+use crate::actions::{ActionState, GameControl};
+use crate::audio::{Ambient, Sfx};
+use crate::loading::Fonts;
+use crate::menu::{click_change_state, spawn_button, ChangeState};
+use crate::settings::SettingsState;
+use crate::{GameState, PauseState};
+
+pub struct PausePlugin;
+
+/// This plugin pauses and resumes the game.
+/// While paused, virtual time is stopped, which freezes physics, timers, particles and animations.
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                toggle_pause.run_if(in_state(SettingsState::Closed)),
+                pause_on_suspend,
+                click_change_state::<PauseState>,
+            )
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(OnEnter(PauseState::Paused), (pause_game, setup_pause_menu))
+        .add_systems(OnExit(PauseState::Paused), resume_game)
+        .add_systems(OnEnter(PauseState::LevelUp), pause_game)
+        .add_systems(OnExit(PauseState::LevelUp), resume_game);
+    }
+}
+
+/// Leaves [`PauseState::Running`] with the clock stopped right away. Otherwise the frame until
+/// the state is entered would still advance the gameplay, and replays don't record it.
+pub fn pause_now(
+    state: PauseState,
+    next_state: &mut NextState<PauseState>,
+    time: &mut Time<Virtual>,
+) {
+    time.pause();
+    next_state.set(state);
+}
+
+fn toggle_pause(
+    action_state: Res<ActionState>,
+    pause_state: Res<State<PauseState>>,
+    mut next_state: ResMut<NextState<PauseState>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if action_state.just_pressed(GameControl::Pause) {
+        match pause_state.get() {
+            PauseState::Running => pause_now(PauseState::Paused, &mut next_state, &mut time),
+            PauseState::Paused => next_state.set(PauseState::Running),
+            // An upgrade has to be picked first
+            PauseState::LevelUp => (),
+        }
+    }
+}
+
+/// Mobile apps losing focus are suspended, don't let the player die in the meantime
+fn pause_on_suspend(
+    mut lifecycle_events: EventReader<AppLifecycle>,
+    mut next_state: ResMut<NextState<PauseState>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    for event in lifecycle_events.read() {
+        if matches!(event, AppLifecycle::WillSuspend | AppLifecycle::Suspended) {
+            pause_now(PauseState::Paused, &mut next_state, &mut time);
+        }
+    }
+}
+
+fn pause_game(
+    mut time: ResMut<Time<Virtual>>,
+    sfx: Res<AudioChannel<Sfx>>,
+    ambient: Res<AudioChannel<Ambient>>,
+) {
+    time.pause();
+    sfx.pause();
+    ambient.pause();
+}
+
+fn resume_game(
+    mut time: ResMut<Time<Virtual>>,
+    sfx: Res<AudioChannel<Sfx>>,
+    ambient: Res<AudioChannel<Ambient>>,
+) {
+    time.unpause();
+    sfx.resume();
+    ambient.resume();
+}
+
+fn setup_pause_menu(mut commands: Commands, fonts: Res<Fonts>) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(Color::linear_rgba(0.0, 0.0, 0.0, 0.6)),
+            GlobalZIndex(1),
+            StateScoped(PauseState::Paused),
+        ))
+        .with_children(|children| {
+            children.spawn((
+                Text::new("Paused"),
+                TextFont {
+                    font: fonts.font.clone(),
+                    font_size: 64.0,
+                    ..default()
+                },
+            ));
+            spawn_button(children, "Resume", ChangeState(PauseState::Running));
+            spawn_button(children, "Settings", ChangeState(SettingsState::Open));
+            spawn_button(children, "Menu", ChangeState(GameState::Menu));
+        });
+}
@@ -8,7 +8,7 @@ pub enum CollisionLayer {
     Player,
     Enemy,
     EnemyProjectile,
-    //Item,
+    Pickup,
     //Ground,
 }
 
@@ -0,0 +1,372 @@
+//! Items lying around for the players to collect: XP orbs, potions, weapons and the like.
+//! Enemies drop them when they die, see the drop tables in `assets/enemies.drops.ron`.
+//! The map places them with objects of the class `pickup`, see `crate::tilemap`.
+
+use avian2d::prelude::*;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use rand::Rng;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::actions::{Actions, CombatSet, DeathEvent, Health};
+use crate::enemies::EnemyForm;
+use crate::loading::DropTableAssets;
+use crate::physics::CollisionLayer;
+use crate::player::{Player, PlayerForm};
+use crate::progression::Experience;
+use crate::seed::RunRng;
+use crate::{GameState, PauseState};
+
+/// Pickups closer than this to a player fly towards them
+const ATTRACT_RADIUS: f32 = 40.0;
+const ATTRACT_SPEED: f32 = 150.0;
+/// Dropped pickups disappear after this long
+const DROP_LIFETIME_SECS: f32 = 15.0;
+/// For this long before they disappear, they blink
+const BLINK_SECS: f32 = 3.0;
+const BLINK_INTERVAL_SECS: f32 = 0.2;
+
+pub struct PickupsPlugin;
+
+/// This plugin drops loot from dying enemies and applies what the players collect
+impl Plugin for PickupsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<DropTables>()
+            .register_asset_loader(DropTablesLoader)
+            .add_systems(
+                FixedUpdate,
+                (
+                    drop_loot,
+                    expire_pickups,
+                    attract_pickups,
+                    collect_pickups,
+                    tick_temporary_forms,
+                    tick_score_multipliers,
+                )
+                    .chain()
+                    .after(CombatSet)
+                    .run_if(in_state(PauseState::Running)),
+            );
+    }
+}
+
+#[derive(Component, Deserialize, Clone, Copy, Debug)]
+pub enum Pickup {
+    Xp(u32),
+    /// Restores health, up to the maximum
+    Heal(u32),
+    /// Turns the player into the form for a while
+    Form {
+        form: PlayerForm,
+        secs: f32,
+    },
+    /// Kills score more for a while
+    ScoreMultiplier {
+        factor: u32,
+        secs: f32,
+    },
+    /// Pulls in every pickup on the map
+    Magnet,
+}
+
+impl Pickup {
+    fn color(self) -> Color {
+        match self {
+            Pickup::Xp(_) => Color::srgb(0.3, 1.0, 0.8),
+            Pickup::Heal(_) => Color::srgb(1.0, 0.2, 0.3),
+            Pickup::Form { .. } => Color::srgb(0.8, 0.6, 0.3),
+            Pickup::ScoreMultiplier { .. } => Color::srgb(1.0, 0.85, 0.1),
+            Pickup::Magnet => Color::srgb(0.6, 0.4, 1.0),
+        }
+    }
+
+    fn size(self) -> f32 {
+        match self {
+            Pickup::Xp(_) => 3.0,
+            _ => 6.0,
+        }
+    }
+}
+
+/// Despawns the pickup when finished
+#[derive(Component, Deref, DerefMut)]
+pub struct PickupLifetime(pub Timer);
+
+/// Flies to the closest player, no matter how far away
+#[derive(Component)]
+pub struct Magnetized;
+
+/// The player is in this form until the timer finishes, then changes back
+#[derive(Component)]
+pub struct TemporaryForm {
+    pub form: PlayerForm,
+    pub previous: PlayerForm,
+    pub timer: Timer,
+}
+
+/// Kills of the player score `factor` times until the timer finishes
+#[derive(Component)]
+pub struct ScoreMultiplier {
+    pub factor: u32,
+    pub timer: Timer,
+}
+
+/// What each kind of enemy drops. Every entry is rolled on its own.
+#[derive(Asset, TypePath, Deserialize, Default)]
+pub struct DropTables {
+    pub melee: Vec<Drop>,
+    pub ranged: Vec<Drop>,
+}
+
+impl DropTables {
+    pub fn for_enemy(&self, form: EnemyForm) -> &[Drop] {
+        match form {
+            EnemyForm::Melee => &self.melee,
+            EnemyForm::Ranged => &self.ranged,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Drop {
+    /// From 0 to 1
+    pub chance: f64,
+    pub pickup: Pickup,
+}
+
+#[derive(Default)]
+struct DropTablesLoader;
+
+#[derive(Debug, Error)]
+pub enum DropTablesError {
+    #[error("Could not read drop tables: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse drop tables: {0}")]
+    Ron(#[from] ron::de::SpannedError),
+}
+
+impl AssetLoader for DropTablesLoader {
+    type Asset = DropTables;
+    type Settings = ();
+    type Error = DropTablesError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        // Plain `.ron` are the particle effects
+        &["drops.ron"]
+    }
+}
+
+/// Spawns a pickup that stays until collected, like the ones placed in the map
+pub fn spawn_pickup(commands: &mut Commands, pickup: Pickup, position: Vec2) -> Entity {
+    commands
+        .spawn((
+            pickup,
+            Sprite {
+                color: pickup.color(),
+                custom_size: Some(Vec2::splat(pickup.size())),
+                ..default()
+            },
+            Transform::from_translation(position.extend(1.0)),
+            RigidBody::Kinematic,
+            Collider::circle(pickup.size()),
+            Sensor,
+            CollisionLayers::new(CollisionLayer::Pickup, [CollisionLayer::Player]),
+            StateScoped(GameState::Playing),
+        ))
+        .id()
+}
+
+/// Spawns a pickup that disappears if not collected in time
+pub fn spawn_drop(commands: &mut Commands, pickup: Pickup, position: Vec2) -> Entity {
+    let entity = spawn_pickup(commands, pickup, position);
+    commands
+        .entity(entity)
+        .insert(PickupLifetime(Timer::from_seconds(
+            DROP_LIFETIME_SECS,
+            TimerMode::Once,
+        )));
+    entity
+}
+
+/// Every enemy drops XP, the rest comes from the drop tables
+fn drop_loot(
+    mut commands: Commands,
+    mut death_events: EventReader<DeathEvent>,
+    drop_table_assets: Res<DropTableAssets>,
+    drop_tables: Res<Assets<DropTables>>,
+    mut run_rng: ResMut<RunRng>,
+) {
+    let tables = drop_tables.get(&drop_table_assets.enemies);
+    for event in death_events.read() {
+        let Some(form) = event.enemy else {
+            continue;
+        };
+        let xp = match form {
+            EnemyForm::Melee => 1,
+            EnemyForm::Ranged => 2,
+        };
+        spawn_drop(&mut commands, Pickup::Xp(xp), event.position);
+        let Some(tables) = tables else {
+            continue;
+        };
+        let rng = run_rng.stream("drops");
+        let mut dropped = 0;
+        for drop in tables.for_enemy(form) {
+            if rng.gen_bool(drop.chance.clamp(0.0, 1.0)) {
+                // Spread out, so they don't all overlap the XP
+                dropped += 1;
+                let offset = Vec2::from_angle(dropped as f32 * 2.0) * 8.0;
+                spawn_drop(&mut commands, drop.pickup, event.position + offset);
+            }
+        }
+    }
+}
+
+// `is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn expire_pickups(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pickup_query: Query<(Entity, &mut PickupLifetime, &mut Visibility)>,
+) {
+    for (entity, mut lifetime, mut visibility) in pickup_query.iter_mut() {
+        lifetime.tick(time.delta());
+        if lifetime.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let remaining = lifetime.remaining_secs();
+        *visibility = if remaining < BLINK_SECS && (remaining / BLINK_INTERVAL_SECS) as u32 % 2 == 0
+        {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+}
+
+fn attract_pickups(
+    mut pickup_query: Query<(&Transform, &mut LinearVelocity, Has<Magnetized>), With<Pickup>>,
+    player_query: Query<&Transform, (With<Player>, With<Health>)>,
+) {
+    for (pickup_transform, mut velocity, magnetized) in pickup_query.iter_mut() {
+        let pickup_position = pickup_transform.translation.xy();
+        let closest = player_query
+            .iter()
+            .map(|transform| transform.translation.xy() - pickup_position)
+            .filter(|delta| magnetized || delta.length() < ATTRACT_RADIUS)
+            .min_by(|a, b| a.length().total_cmp(&b.length()));
+        velocity.0 = closest.map_or(Vec2::ZERO, |delta| {
+            delta.normalize_or_zero() * ATTRACT_SPEED
+        });
+    }
+}
+
+fn collect_pickups(
+    mut commands: Commands,
+    mut collision_event_reader: EventReader<Collision>,
+    pickup_query: Query<(Entity, &Pickup)>,
+    mut player_query: Query<(
+        &Player,
+        &mut Health,
+        &mut Experience,
+        Option<&TemporaryForm>,
+    )>,
+) {
+    // Two players can touch the same pickup in one tick
+    let mut collected = HashSet::new();
+    for Collision(contacts) in collision_event_reader.read() {
+        let (pickup_entity, player_entity) = if pickup_query.contains(contacts.entity1) {
+            (contacts.entity1, contacts.entity2)
+        } else {
+            (contacts.entity2, contacts.entity1)
+        };
+        let (Ok((_, pickup)), Ok((player, mut health, mut experience, temporary_form))) = (
+            pickup_query.get(pickup_entity),
+            player_query.get_mut(player_entity),
+        ) else {
+            continue;
+        };
+        if !collected.insert(pickup_entity) {
+            continue;
+        }
+        commands.entity(pickup_entity).despawn();
+        match *pickup {
+            Pickup::Xp(xp) => experience.add(xp),
+            Pickup::Heal(amount) => {
+                health.health = (health.health + amount).min(health.max_health);
+            }
+            Pickup::Form { form, secs } => {
+                commands.entity(player_entity).insert(TemporaryForm {
+                    form,
+                    // Another temporary form is replaced, not returned to
+                    previous: temporary_form.map_or(player.form, |current| current.previous),
+                    timer: Timer::from_seconds(secs, TimerMode::Once),
+                });
+            }
+            Pickup::ScoreMultiplier { factor, secs } => {
+                commands.entity(player_entity).insert(ScoreMultiplier {
+                    factor,
+                    timer: Timer::from_seconds(secs, TimerMode::Once),
+                });
+            }
+            Pickup::Magnet => {
+                for (entity, _) in pickup_query.iter() {
+                    if !collected.contains(&entity) {
+                        commands.entity(entity).insert(Magnetized);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Changes the form once the player isn't busy, and back when the time is up
+fn tick_temporary_forms(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut player_query: Query<(Entity, &Player, &mut Actions, &mut TemporaryForm)>,
+) {
+    for (entity, player, mut actions, mut temporary_form) in player_query.iter_mut() {
+        temporary_form.timer.tick(time.delta());
+        let form = if temporary_form.timer.finished() {
+            temporary_form.previous
+        } else {
+            temporary_form.form
+        };
+        if player.form != form {
+            if matches!(*actions, Actions::Idle) {
+                *actions = Actions::ChangePlayerForm(form);
+            }
+        } else if temporary_form.timer.finished() {
+            commands.entity(entity).remove::<TemporaryForm>();
+        }
+    }
+}
+
+fn tick_score_multipliers(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut player_query: Query<(Entity, &mut ScoreMultiplier)>,
+) {
+    for (entity, mut multiplier) in player_query.iter_mut() {
+        multiplier.timer.tick(time.delta());
+        if multiplier.timer.finished() {
+            commands.entity(entity).remove::<ScoreMultiplier>();
+        }
+    }
+}
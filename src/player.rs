@@ -2,52 +2,172 @@ use avian2d::prelude::*;
 use bevy::math::vec3;
 use bevy::prelude::*;
 use bevy_aseprite_ultra::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::actions::{Actions, Health};
+use crate::actions::{ActionState, Actions, GameControl, Health, InputBindings, PlayerDevices};
 use crate::loading::{Fonts, TextureAssets};
 use crate::physics::CollisionLayer;
+use crate::progression::{Experience, Upgrades};
+use crate::replay::Playback;
 use crate::ui::CooldownDisplay;
-use crate::GameState;
+use crate::{GameState, PauseState};
 
 pub struct PlayerPlugin;
 
 #[derive(Default, Component)]
-#[require(RigidBody)]
+#[require(RigidBody, Experience, Upgrades)]
 pub struct Player {
+    /// 0 for the first player, the others count up in the order they joined
+    pub id: usize,
     pub form: PlayerForm,
     pub score: u32,
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
 pub enum PlayerForm {
     #[default]
     Sword,
     Bow,
 }
 
+impl PlayerForm {
+    pub fn next(self) -> Self {
+        match self {
+            PlayerForm::Sword => PlayerForm::Bow,
+            PlayerForm::Bow => PlayerForm::Sword,
+        }
+    }
+}
+
+/// The player can only dash again once this is finished
+#[derive(Component, Deref, DerefMut)]
+pub struct DashCooldown(pub Timer);
+
+impl Default for DashCooldown {
+    fn default() -> Self {
+        let mut timer = Timer::from_seconds(0.8, TimerMode::Once);
+        // Ready right away
+        timer.tick(timer.duration());
+        Self(timer)
+    }
+}
+
+pub const MAX_PLAYERS: usize = 4;
+
+/// Tints the players and their HUD to tell them apart
+pub const PLAYER_COLORS: [Color; MAX_PLAYERS] = [
+    Color::WHITE,
+    Color::srgb(0.5, 0.7, 1.0),
+    Color::srgb(0.6, 1.0, 0.5),
+    Color::srgb(1.0, 0.8, 0.4),
+];
+
+/// Shows the aim of the player it belongs to
 #[derive(Component)]
-pub struct DirectionArrow;
+pub struct DirectionArrow(pub Entity);
+
+/// Normalized direction the player aims at, independent of the movement
+#[derive(Component, Default)]
+pub struct Aim(pub Option<Vec2>);
 
 /// This plugin handles player related stuff like movement
 /// Player logic is only active during the State `GameState::Playing`
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Playing), spawn_player)
+        app.add_systems(OnEnter(GameState::Playing), spawn_first_player)
             .add_systems(
                 Update,
-                update_direction_arrow.run_if(in_state(GameState::Playing)),
+                (
+                    join_players
+                        .run_if(in_state(PauseState::Running))
+                        .run_if(not(resource_exists::<Playback>)),
+                    update_direction_arrows,
+                )
+                    .run_if(in_state(GameState::Playing)),
             );
     }
 }
 
-fn spawn_player(mut commands: Commands, textures: Res<TextureAssets>, fonts: Res<Fonts>) {
+fn spawn_first_player(mut commands: Commands, textures: Res<TextureAssets>, fonts: Res<Fonts>) {
+    spawn_player(
+        &mut commands,
+        &textures,
+        &fonts,
+        0,
+        PlayerDevices::Shared,
+        Vec2::ZERO,
+    );
+}
+
+/// Gamepads not used by a player yet join with [`GameControl::Join`]
+fn join_players(
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    fonts: Res<Fonts>,
+    bindings: Res<InputBindings>,
+    gamepad_query: Query<(Entity, &Gamepad)>,
+    player_query: Query<(&Player, &PlayerDevices, &Transform, Has<Health>)>,
+) {
+    for (gamepad_entity, gamepad) in gamepad_query.iter() {
+        if !gamepad.any_just_pressed(bindings.buttons(GameControl::Join).iter().copied())
+            || player_query
+                .iter()
+                .any(|(_, devices, ..)| *devices == PlayerDevices::Gamepad(gamepad_entity))
+        {
+            continue;
+        }
+        let Some(id) = (0..MAX_PLAYERS).find(|id| player_query.iter().all(|(p, ..)| p.id != *id))
+        else {
+            return;
+        };
+        let position = join_position(
+            player_query
+                .iter()
+                .map(|(_, _, transform, alive)| (transform, alive)),
+            id,
+        );
+        spawn_player(
+            &mut commands,
+            &textures,
+            &fonts,
+            id,
+            PlayerDevices::Gamepad(gamepad_entity),
+            position,
+        );
+        // Only one per frame, so two gamepads can't get the same id
+        return;
+    }
+}
+
+/// Next to someone still alive, given the transforms of the players and whether they are alive
+pub fn join_position<'a>(players: impl Iterator<Item = (&'a Transform, bool)>, id: usize) -> Vec2 {
+    let position = players
+        .filter(|(_, alive)| *alive)
+        .map(|(transform, _)| transform.translation.xy())
+        .next()
+        .unwrap_or(Vec2::ZERO);
+    position + Vec2::from_angle(id as f32 * 1.5) * 20.0
+}
+
+pub fn spawn_player(
+    commands: &mut Commands,
+    textures: &TextureAssets,
+    fonts: &Fonts,
+    id: usize,
+    devices: PlayerDevices,
+    position: Vec2,
+) {
     commands
         .spawn((
             AseSpriteAnimation {
                 aseprite: textures.player_sword.clone(),
                 animation: Animation::tag("flaming"),
             },
-            Transform::from_translation(Vec3::new(0., 0., 2.)),
+            Sprite {
+                color: PLAYER_COLORS[id],
+                ..default()
+            },
+            Transform::from_translation(position.extend(2.)),
             Collider::circle(5.0),
             CollisionLayers::new(
                 CollisionLayer::Player,
@@ -55,17 +175,22 @@ fn spawn_player(mut commands: Commands, textures: Res<TextureAssets>, fonts: Res
                     CollisionLayer::Default,
                     CollisionLayer::Enemy,
                     CollisionLayer::EnemyProjectile,
+                    CollisionLayer::Pickup,
                 ],
             ),
             LockedAxes::ROTATION_LOCKED,
             LinearDamping(10.0),
-            Player::default(),
+            Player { id, ..default() },
+            devices,
+            ActionState::default(),
             Health {
                 owner: 0,
                 max_health: 5,
                 health: 5,
             },
             Actions::default(),
+            DashCooldown::default(),
+            Aim::default(),
             StateScoped(GameState::Playing),
         ))
         .with_children(|commands| {
@@ -84,42 +209,49 @@ fn spawn_player(mut commands: Commands, textures: Res<TextureAssets>, fonts: Res
         });
 }
 
-fn update_direction_arrow(
+fn update_direction_arrows(
     textures: Res<TextureAssets>,
-    mut arrow_query: Query<(Entity, &mut Transform), (With<DirectionArrow>, Without<Player>)>,
-    player_query: Query<(&Actions, &Transform), With<Player>>,
+    mut arrow_query: Query<(Entity, &DirectionArrow, &mut Transform), Without<Player>>,
+    player_query: Query<(Entity, Option<&Actions>, &Aim, &Transform), With<Player>>,
     mut commands: Commands,
 ) {
-    let Ok((actions, player_transform)) = player_query.get_single() else {
-        return;
-    };
-    let Actions::Charging {
-        charge,
-        trigger_direction: Some(player_direction),
-    } = actions
-    else {
-        // No direction selected: remove arrow display
-        for (entity, _) in arrow_query.iter() {
-            commands.entity(entity).despawn();
-        }
-        return;
-    };
+    for (player_entity, actions, aim, player_transform) in player_query.iter() {
+        let arrow = arrow_query
+            .iter_mut()
+            .find(|(_, DirectionArrow(owner), _)| *owner == player_entity);
+        // While charging, the arrow grows with the charge. Otherwise it just shows the aim.
+        let (player_direction, charge_fraction) = match actions {
+            Some(Actions::Charging {
+                charge,
+                trigger_direction,
+            }) => (*trigger_direction, charge.fraction()),
+            Some(_) => (aim.0, 0.0),
+            None => (None, 0.0),
+        };
+        let Some(player_direction) = player_direction else {
+            // No direction selected: remove arrow display
+            if let Some((entity, ..)) = arrow {
+                commands.entity(entity).despawn();
+            }
+            continue;
+        };
 
-    let direction = player_direction.normalize().extend(0.0);
-    let as_quat = Quat::from_rotation_arc(Vec3::Y, direction);
-    let mut target_transform = player_transform.with_rotation(as_quat);
-    target_transform.translation += direction * 20.0;
-    target_transform.scale = Vec3::splat(1.0 + charge.fraction());
+        let direction = player_direction.normalize().extend(0.0);
+        let as_quat = Quat::from_rotation_arc(Vec3::Y, direction);
+        let mut target_transform = player_transform.with_rotation(as_quat);
+        target_transform.translation += direction * 20.0;
+        target_transform.scale = Vec3::splat(1.0 + charge_fraction);
 
-    if let Some((_, mut arrow_transform)) = arrow_query.iter_mut().next() {
-        *arrow_transform = target_transform;
-        return;
+        if let Some((_, _, mut arrow_transform)) = arrow {
+            *arrow_transform = target_transform;
+            continue;
+        }
+        // No existing arrow
+        commands.spawn((
+            Sprite::from_image(textures.arrow.clone()),
+            target_transform,
+            DirectionArrow(player_entity),
+            StateScoped(GameState::Playing),
+        ));
     }
-    // No existing arrow
-    commands.spawn((
-        Sprite::from_image(textures.arrow.clone()),
-        target_transform,
-        DirectionArrow,
-        StateScoped(GameState::Playing),
-    ));
 }
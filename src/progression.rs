@@ -0,0 +1,338 @@
+//! Collecting enough XP orbs levels the player up.
+//! Every level-up pauses the game until the player picks one of a few random upgrades.
+
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+
+use crate::actions::{Health, STEP_RADIUS};
+use crate::loading::Fonts;
+use crate::menu::spawn_button;
+use crate::pause::pause_now;
+use crate::player::{Player, PLAYER_COLORS};
+use crate::replay::{Playback, Recording};
+use crate::seed::RunRng;
+use crate::{GameState, PauseState};
+
+/// How many upgrades a level-up offers to pick from
+const UPGRADE_CHOICES: usize = 3;
+
+pub struct ProgressionPlugin;
+
+/// This plugin levels players up and lets them pick upgrades.
+/// While an upgrade is picked, the game is in `PauseState::LevelUp`.
+impl Plugin for ProgressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                start_level_up.run_if(in_state(PauseState::Running)),
+                click_upgrade.run_if(in_state(PauseState::LevelUp)),
+            ),
+        )
+        .add_systems(
+            OnEnter(PauseState::LevelUp),
+            (offer_upgrades, present_offer).chain(),
+        )
+        .add_systems(OnExit(GameState::Playing), |mut commands: Commands| {
+            commands.remove_resource::<UpgradeOffer>();
+        });
+    }
+}
+
+/// Level and XP of a player
+#[derive(Component)]
+pub struct Experience {
+    pub level: u32,
+    /// Collected since the last level-up
+    pub xp: u32,
+    /// Level-ups the player didn't pick an upgrade for yet
+    pub pending_level_ups: u32,
+}
+
+impl Default for Experience {
+    fn default() -> Self {
+        Self {
+            level: 1,
+            xp: 0,
+            pending_level_ups: 0,
+        }
+    }
+}
+
+impl Experience {
+    /// XP needed for the next level
+    pub fn needed(&self) -> u32 {
+        3 + 2 * (self.level - 1)
+    }
+
+    pub fn add(&mut self, xp: u32) {
+        self.xp += xp;
+        while self.xp >= self.needed() {
+            self.xp -= self.needed();
+            self.level += 1;
+            self.pending_level_ups += 1;
+        }
+    }
+}
+
+/// What the picked upgrades changed about the attacks of a player
+#[derive(Component)]
+pub struct Upgrades {
+    /// Swings added to every sword attack
+    pub extra_swings: u32,
+    /// Factor of the cool-down after attacking
+    pub cooldown_factor: f32,
+    /// Radius of the area a sword swing damages
+    pub slash_radius: f32,
+}
+
+impl Default for Upgrades {
+    fn default() -> Self {
+        Self {
+            extra_swings: 0,
+            cooldown_factor: 1.0,
+            slash_radius: STEP_RADIUS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Upgrade {
+    ExtraSwing,
+    ShorterCooldown,
+    BiggerSlash,
+    MaxHealth,
+}
+
+impl Upgrade {
+    /// Replays store the index, only ever append here
+    const ALL: [Upgrade; 4] = [
+        Upgrade::ExtraSwing,
+        Upgrade::ShorterCooldown,
+        Upgrade::BiggerSlash,
+        Upgrade::MaxHealth,
+    ];
+
+    pub fn index(self) -> u8 {
+        Self::ALL
+            .iter()
+            .position(|upgrade| *upgrade == self)
+            .expect("Upgrade missing in ALL") as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Upgrade::ExtraSwing => "Swing",
+            Upgrade::ShorterCooldown => "Haste",
+            Upgrade::BiggerSlash => "Reach",
+            Upgrade::MaxHealth => "Vigor",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Upgrade::ExtraSwing => "One more swing per attack",
+            Upgrade::ShorterCooldown => "20% shorter cool-down",
+            Upgrade::BiggerSlash => "20% bigger slashes",
+            Upgrade::MaxHealth => "One more heart, filled up",
+        }
+    }
+
+    fn apply(self, upgrades: &mut Upgrades, health: &mut Health) {
+        match self {
+            Upgrade::ExtraSwing => upgrades.extra_swings += 1,
+            Upgrade::ShorterCooldown => upgrades.cooldown_factor *= 0.8,
+            Upgrade::BiggerSlash => upgrades.slash_radius *= 1.2,
+            Upgrade::MaxHealth => {
+                health.max_health += 1;
+                health.health += 1;
+            }
+        }
+    }
+}
+
+/// The upgrades offered at the current level-up. It outlives pausing in between, so the offer
+/// doesn't change by pausing.
+#[derive(Resource)]
+pub struct UpgradeOffer {
+    pub player: Entity,
+    pub choices: Vec<Upgrade>,
+}
+
+/// Pressing a button with this component picks the upgrade
+#[derive(Component)]
+pub struct ChooseUpgrade(pub Upgrade);
+
+fn start_level_up(
+    player_query: Query<&Experience, (With<Player>, With<Health>)>,
+    mut next_state: ResMut<NextState<PauseState>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if player_query
+        .iter()
+        .any(|experience| experience.pending_level_ups > 0)
+    {
+        pause_now(PauseState::LevelUp, &mut next_state, &mut time);
+    }
+}
+
+/// Rolls the upgrades for the first player with a pending level-up
+fn offer_upgrades(
+    mut commands: Commands,
+    offer: Option<Res<UpgradeOffer>>,
+    player_query: Query<(Entity, &Player, &Experience), With<Health>>,
+    mut run_rng: ResMut<RunRng>,
+) {
+    if offer.is_some() {
+        return;
+    }
+    let Some((player_entity, ..)) = player_query
+        .iter()
+        .filter(|(.., experience)| experience.pending_level_ups > 0)
+        .min_by_key(|(_, player, _)| player.id)
+    else {
+        return;
+    };
+    let choices = Upgrade::ALL
+        .choose_multiple(run_rng.stream("upgrades"), UPGRADE_CHOICES)
+        .copied()
+        .collect();
+    commands.insert_resource(UpgradeOffer {
+        player: player_entity,
+        choices,
+    });
+}
+
+/// Replays pick what was picked in the recording, otherwise the player is asked
+fn present_offer(
+    mut commands: Commands,
+    offer: Option<Res<UpgradeOffer>>,
+    playback: Option<ResMut<Playback>>,
+    mut player_query: Query<(&Player, &mut Experience, &mut Upgrades, &mut Health)>,
+    mut next_state: ResMut<NextState<PauseState>>,
+    fonts: Res<Fonts>,
+) {
+    let Some(offer) = offer else {
+        next_state.set(PauseState::Running);
+        return;
+    };
+    if let Some(upgrade) = playback.and_then(|mut playback| playback.next_upgrade()) {
+        pick_upgrade(
+            &mut commands,
+            &offer,
+            upgrade,
+            &mut player_query,
+            &mut next_state,
+        );
+        return;
+    }
+    let Ok((player, experience, ..)) = player_query.get(offer.player) else {
+        return;
+    };
+    let text_font = TextFont {
+        font: fonts.font.clone(),
+        font_size: 24.0,
+        ..default()
+    };
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(Color::linear_rgba(0.0, 0.0, 0.0, 0.6)),
+            GlobalZIndex(1),
+            StateScoped(PauseState::LevelUp),
+        ))
+        .with_children(|children| {
+            children.spawn((
+                Text::new("Level up!"),
+                TextFont {
+                    font: fonts.font.clone(),
+                    font_size: 64.0,
+                    ..default()
+                },
+            ));
+            children.spawn((
+                Text::new(format!(
+                    "Player {} reached level {}",
+                    player.id + 1,
+                    experience.level + 1 - experience.pending_level_ups
+                )),
+                text_font.clone(),
+                TextColor(PLAYER_COLORS[player.id]),
+            ));
+            for upgrade in &offer.choices {
+                children
+                    .spawn(Node {
+                        align_items: AlignItems::Center,
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        spawn_button(row, upgrade.name(), ChooseUpgrade(*upgrade));
+                        row.spawn((
+                            Text::new(upgrade.description()),
+                            text_font.clone(),
+                            Node {
+                                width: Val::Px(300.0),
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+fn click_upgrade(
+    mut commands: Commands,
+    interaction_query: Query<(&Interaction, &ChooseUpgrade), (Changed<Interaction>, With<Button>)>,
+    offer: Option<Res<UpgradeOffer>>,
+    recording: Option<ResMut<Recording>>,
+    mut player_query: Query<(&Player, &mut Experience, &mut Upgrades, &mut Health)>,
+    mut next_state: ResMut<NextState<PauseState>>,
+) {
+    let Some(offer) = offer else {
+        return;
+    };
+    let Some((_, ChooseUpgrade(upgrade))) = interaction_query
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+    else {
+        return;
+    };
+    if let Some(mut recording) = recording {
+        recording.record_upgrade(*upgrade);
+    }
+    pick_upgrade(
+        &mut commands,
+        &offer,
+        *upgrade,
+        &mut player_query,
+        &mut next_state,
+    );
+}
+
+fn pick_upgrade(
+    commands: &mut Commands,
+    offer: &UpgradeOffer,
+    upgrade: Upgrade,
+    player_query: &mut Query<(&Player, &mut Experience, &mut Upgrades, &mut Health)>,
+    next_state: &mut NextState<PauseState>,
+) {
+    if let Ok((_, mut experience, mut upgrades, mut health)) = player_query.get_mut(offer.player) {
+        experience.pending_level_ups = experience.pending_level_ups.saturating_sub(1);
+        upgrade.apply(&mut upgrades, &mut health);
+    }
+    commands.remove_resource::<UpgradeOffer>();
+    // Further pending level-ups start right away again
+    next_state.set(PauseState::Running);
+}
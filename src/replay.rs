@@ -0,0 +1,566 @@
+//! Records the input of every run and plays it back.
+//! A replay is the seed of the run plus the input and time step of every frame. As long as the
+//! gameplay only depends on those, playing it back reproduces the run exactly.
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::HashSet;
+use bevy_rand::prelude::{GlobalEntropy, WyRand};
+use rand::Rng;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::actions::{ActionState, ActionStateSet, GameControl, Health, PlayerDevices};
+use crate::loading::{Fonts, TextureAssets};
+use crate::player::{join_position, spawn_player, Player};
+use crate::progression::Upgrade;
+use crate::seed::{RunRng, RunSeed};
+use crate::storage::Storage;
+use crate::{GameState, LaunchOptions, PauseState};
+
+const MAGIC: &[u8; 4] = b"BSRP";
+/// Bump this when the format changes, old replays won't play back the same anyway
+const REPLAY_VERSION: u8 = 4;
+/// The replay of the last run is stored compressed under this name
+const LAST_RUN: &str = "last_run";
+/// A longer last run is not stored, browsers only give the game a few megabytes of local storage
+/// and the settings and high scores need room as well
+const MAX_STORED_LAST_RUN_BYTES: usize = 1024 * 1024;
+
+pub struct ReplayPlugin;
+
+/// This plugin records every run and plays back replays from the menu or the command line.
+/// While playing back, the frame time comes from the replay instead of the clock.
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let last_run = app
+            .world()
+            .resource::<Storage>()
+            .load_bytes(LAST_RUN)
+            .and_then(|bytes| miniz_oxide::inflate::decompress_to_vec(&bytes).ok())
+            .and_then(|bytes| Replay::decode(&bytes).ok());
+        app.init_resource::<RunSeed>()
+            .insert_resource(RunRng::new(0))
+            .init_resource::<PendingReplay>()
+            .insert_resource(LastRun(last_run))
+            .add_systems(Startup, load_launch_replay)
+            .add_systems(OnEnter(GameState::Playing), start_run)
+            .add_systems(OnExit(GameState::Playing), finish_run)
+            .add_systems(
+                PreUpdate,
+                play_back_input
+                    .after(ActionStateSet)
+                    .run_if(resource_exists::<Playback>),
+            )
+            .add_systems(
+                Update,
+                (
+                    play_back_joins
+                        .run_if(resource_exists::<Playback>)
+                        .run_if(in_state(PauseState::Running)),
+                    (click_watch_button, start_pending_replay)
+                        .chain()
+                        .run_if(in_state(GameState::Menu)),
+                ),
+            )
+            .add_systems(
+                Last,
+                (
+                    record_frame.run_if(resource_exists::<Recording>),
+                    advance_playback.run_if(resource_exists::<Playback>),
+                )
+                    .run_if(in_state(PauseState::Running)),
+            );
+    }
+}
+
+/// Played back when the next run starts
+#[derive(Resource, Default)]
+struct PendingReplay(Option<Replay>);
+
+/// The replay of the last finished run, if any
+#[derive(Resource)]
+pub struct LastRun(pub Option<Replay>);
+
+/// Starts playing back the [`LastRun`] when pressed
+#[derive(Component)]
+pub struct WatchLastRun;
+
+/// The run in progress is recorded here
+#[derive(Resource)]
+pub struct Recording(Replay);
+
+impl Recording {
+    pub fn record_upgrade(&mut self, upgrade: Upgrade) {
+        self.0.upgrades.push(upgrade);
+    }
+}
+
+/// The run in progress is a replay, `frame` is the current one
+#[derive(Resource)]
+pub struct Playback {
+    replay: Replay,
+    frame: usize,
+    upgrade: usize,
+}
+
+impl Playback {
+    /// The upgrade picked at the next level-up, if the recording got that far
+    pub fn next_upgrade(&mut self) -> Option<Upgrade> {
+        let upgrade = self.replay.upgrades.get(self.upgrade).copied();
+        self.upgrade += 1;
+        upgrade
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Replay {
+    seed: u64,
+    frames: Vec<ReplayFrame>,
+    /// Picked at the level-ups, in order. Which ones were offered follows from the seed.
+    upgrades: Vec<Upgrade>,
+}
+
+#[derive(Clone)]
+struct ReplayFrame {
+    delta: Duration,
+    /// The combined controls, the ones menus read
+    controls: ControlBits,
+    players: Vec<PlayerFrame>,
+}
+
+#[derive(Clone)]
+struct PlayerFrame {
+    id: u8,
+    /// The just pressed bits are the triggered controls, since gameplay reads those
+    controls: ControlBits,
+    movement: Vec2,
+    move_target: Option<Vec2>,
+    aim_direction: Option<Vec2>,
+    aim_target: Option<Vec2>,
+}
+
+/// Pressed controls as bit sets, the bits are the indices in [`GameControl::ALL`]
+#[derive(Clone, Copy)]
+struct ControlBits {
+    pressed: u16,
+    just_pressed: u16,
+}
+
+impl ControlBits {
+    fn of(action_state: &ActionState) -> Self {
+        Self {
+            pressed: Self::bits(|control| action_state.pressed(control)),
+            just_pressed: Self::bits(|control| action_state.just_pressed(control)),
+        }
+    }
+
+    /// The gameplay ticks of the frame may have consumed the triggered controls already,
+    /// so this takes them from the start of the frame
+    fn of_player(action_state: &ActionState) -> Self {
+        Self {
+            pressed: Self::bits(|control| action_state.pressed(control)),
+            just_pressed: Self::bits(|control| action_state.frame_triggered(control)),
+        }
+    }
+
+    /// Takes the control from the devices instead of the replay
+    fn keep(self, control: GameControl, action_state: &ActionState) -> Self {
+        let bit = Self::bits(|other| other == control);
+        let devices = Self::of(action_state);
+        Self {
+            pressed: (self.pressed & !bit) | (devices.pressed & bit),
+            just_pressed: (self.just_pressed & !bit) | (devices.just_pressed & bit),
+        }
+    }
+
+    fn bits(is_set: impl Fn(GameControl) -> bool) -> u16 {
+        GameControl::ALL
+            .into_iter()
+            .enumerate()
+            .filter(|(_, control)| is_set(*control))
+            .fold(0, |set, (i, _)| set | 1 << i)
+    }
+
+    fn apply(self, action_state: &mut ActionState) {
+        let controls = |bits: u16| -> HashSet<GameControl> {
+            GameControl::ALL
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| bits & 1 << i != 0)
+                .map(|(_, control)| control)
+                .collect()
+        };
+        action_state.restore(controls(self.pressed), controls(self.just_pressed));
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("Not a replay file")]
+    NotAReplay,
+    #[error("Replay version {0} is not supported")]
+    UnsupportedVersion(u8),
+    #[error("The replay ends unexpectedly")]
+    Truncated,
+    #[error("The replay contains invalid data")]
+    Corrupt,
+    #[error("Could not read replay: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+// The format is little endian:
+// magic, version: u8, seed: u64, frame count: u32, frames, upgrade count: u16, upgrades: u8
+// frame: delta in nanoseconds: u32, controls, player count: u8, players
+// player: id: u8, controls, flags: u8, movement, then the targets/directions present in flags
+// controls: pressed: u16, just pressed: u16
+const MOVE_TARGET: u8 = 1;
+const AIM_DIRECTION: u8 = 2;
+const AIM_TARGET: u8 = 4;
+
+impl Replay {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(REPLAY_VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        let put_controls = |bytes: &mut Vec<u8>, controls: ControlBits| {
+            bytes.extend_from_slice(&controls.pressed.to_le_bytes());
+            bytes.extend_from_slice(&controls.just_pressed.to_le_bytes());
+        };
+        let put_vec2 = |bytes: &mut Vec<u8>, v: Vec2| {
+            bytes.extend_from_slice(&v.x.to_le_bytes());
+            bytes.extend_from_slice(&v.y.to_le_bytes());
+        };
+        for frame in &self.frames {
+            bytes.extend_from_slice(&(frame.delta.as_nanos() as u32).to_le_bytes());
+            put_controls(&mut bytes, frame.controls);
+            bytes.push(frame.players.len() as u8);
+            for player in &frame.players {
+                bytes.push(player.id);
+                put_controls(&mut bytes, player.controls);
+                let optional = [
+                    (MOVE_TARGET, player.move_target),
+                    (AIM_DIRECTION, player.aim_direction),
+                    (AIM_TARGET, player.aim_target),
+                ];
+                bytes.push(
+                    optional
+                        .iter()
+                        .filter(|(_, v)| v.is_some())
+                        .fold(0, |flags, (flag, _)| flags | flag),
+                );
+                put_vec2(&mut bytes, player.movement);
+                for v in optional.iter().filter_map(|(_, v)| *v) {
+                    put_vec2(&mut bytes, v);
+                }
+            }
+        }
+        bytes.extend_from_slice(&(self.upgrades.len() as u16).to_le_bytes());
+        bytes.extend(self.upgrades.iter().map(|upgrade| upgrade.index()));
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut reader = Reader(bytes);
+        if reader.take::<4>()? != *MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        let [version] = reader.take()?;
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let seed = u64::from_le_bytes(reader.take()?);
+        let frame_count = u32::from_le_bytes(reader.take()?);
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        for _ in 0..frame_count {
+            let delta = Duration::from_nanos(u32::from_le_bytes(reader.take()?) as u64);
+            let controls = reader.controls()?;
+            let [player_count] = reader.take()?;
+            let mut players = Vec::with_capacity(player_count as usize);
+            for _ in 0..player_count {
+                let [id] = reader.take()?;
+                let controls = reader.controls()?;
+                let [flags] = reader.take()?;
+                let movement = reader.vec2()?;
+                let mut optional = |flag| -> Result<Option<Vec2>, ReplayError> {
+                    if flags & flag != 0 {
+                        Ok(Some(reader.vec2()?))
+                    } else {
+                        Ok(None)
+                    }
+                };
+                players.push(PlayerFrame {
+                    id,
+                    controls,
+                    movement,
+                    move_target: optional(MOVE_TARGET)?,
+                    aim_direction: optional(AIM_DIRECTION)?,
+                    aim_target: optional(AIM_TARGET)?,
+                });
+            }
+            frames.push(ReplayFrame {
+                delta,
+                controls,
+                players,
+            });
+        }
+        let upgrade_count = u16::from_le_bytes(reader.take()?);
+        let upgrades = (0..upgrade_count)
+            .map(|_| {
+                let [index] = reader.take()?;
+                Upgrade::from_index(index).ok_or(ReplayError::Corrupt)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            seed,
+            frames,
+            upgrades,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ReplayError> {
+        let (head, rest) = self.0.split_at_checked(N).ok_or(ReplayError::Truncated)?;
+        self.0 = rest;
+        Ok(head.try_into().expect("Split at N"))
+    }
+
+    fn controls(&mut self) -> Result<ControlBits, ReplayError> {
+        Ok(ControlBits {
+            pressed: u16::from_le_bytes(self.take()?),
+            just_pressed: u16::from_le_bytes(self.take()?),
+        })
+    }
+
+    fn vec2(&mut self) -> Result<Vec2, ReplayError> {
+        Ok(Vec2::new(
+            f32::from_le_bytes(self.take()?),
+            f32::from_le_bytes(self.take()?),
+        ))
+    }
+}
+
+fn load_launch_replay(launch_options: Res<LaunchOptions>, mut pending: ResMut<PendingReplay>) {
+    let Some(path) = &launch_options.replay else {
+        return;
+    };
+    match std::fs::read(path)
+        .map_err(ReplayError::from)
+        .and_then(|bytes| Replay::decode(&bytes))
+    {
+        Ok(replay) => pending.0 = Some(replay),
+        Err(e) => error!("Could not play {}: {e}", path.display()),
+    }
+}
+
+fn click_watch_button(
+    last_run: Res<LastRun>,
+    mut pending: ResMut<PendingReplay>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<WatchLastRun>)>,
+) {
+    if interaction_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        pending.0.clone_from(&last_run.0);
+    }
+}
+
+/// The first frame of the replay already needs its time step, so it's set before the run starts
+fn start_pending_replay(
+    mut pending: ResMut<PendingReplay>,
+    mut time_update_strategy: ResMut<TimeUpdateStrategy>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(replay) = &pending.0 else {
+        return;
+    };
+    let Some(first_frame) = replay.frames.first() else {
+        warn!("Not playing the replay, it is empty");
+        pending.0 = None;
+        return;
+    };
+    *time_update_strategy = TimeUpdateStrategy::ManualDuration(first_frame.delta);
+    next_state.set(GameState::Playing);
+}
+
+fn start_run(
+    mut commands: Commands,
+    mut pending: ResMut<PendingReplay>,
+    mut entropy: GlobalEntropy<WyRand>,
+    launch_options: Res<LaunchOptions>,
+    mut run_seed: ResMut<RunSeed>,
+    mut run_rng: ResMut<RunRng>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+) {
+    // The first tick has to come at the same time in the recording and when playing it back
+    let overstep = fixed_time.overstep();
+    fixed_time.discard_overstep(overstep);
+
+    if let Some(replay) = pending.0.take() {
+        *run_seed = RunSeed {
+            seed: replay.seed,
+            daily: None,
+        };
+        commands.insert_resource(Playback {
+            replay,
+            frame: 0,
+            upgrade: 0,
+        });
+    } else {
+        *run_seed = match &launch_options.seed {
+            Some(option) => RunSeed::from_option(option),
+            None => RunSeed {
+                seed: entropy.gen(),
+                daily: None,
+            },
+        };
+        commands.insert_resource(Recording(Replay {
+            seed: run_seed.seed,
+            frames: Vec::new(),
+            upgrades: Vec::new(),
+        }));
+    }
+    *run_rng = RunRng::new(run_seed.seed);
+}
+
+fn finish_run(
+    mut commands: Commands,
+    recording: Option<Res<Recording>>,
+    playback: Option<Res<Playback>>,
+    launch_options: Res<LaunchOptions>,
+    mut last_run: ResMut<LastRun>,
+    storage: Res<Storage>,
+    mut time_update_strategy: ResMut<TimeUpdateStrategy>,
+) {
+    if playback.is_some() {
+        commands.remove_resource::<Playback>();
+        *time_update_strategy = TimeUpdateStrategy::Automatic;
+    }
+    let Some(recording) = recording else {
+        return;
+    };
+    commands.remove_resource::<Recording>();
+    let bytes = recording.0.encode();
+    let compressed = miniz_oxide::deflate::compress_to_vec(&bytes, 6);
+    if compressed.len() > MAX_STORED_LAST_RUN_BYTES {
+        warn!("The run is too long to be stored as the last run");
+        storage.remove_bytes(LAST_RUN);
+    } else {
+        storage.save_bytes(LAST_RUN, &compressed);
+    }
+    if let Some(path) = &launch_options.record {
+        if let Err(e) = std::fs::write(path, &bytes) {
+            warn!("Could not write replay to {}: {e}", path.display());
+        }
+    }
+    last_run.0 = Some(recording.0.clone());
+}
+
+fn record_frame(
+    mut recording: ResMut<Recording>,
+    time: Res<Time<Virtual>>,
+    action_state: Res<ActionState>,
+    player_query: Query<(&Player, &ActionState)>,
+) {
+    let players = player_query
+        .iter()
+        .map(|(player, action_state)| PlayerFrame {
+            id: player.id as u8,
+            controls: ControlBits::of_player(action_state),
+            movement: action_state.movement,
+            move_target: action_state.move_target,
+            aim_direction: action_state.aim_direction,
+            aim_target: action_state.aim_target,
+        })
+        .collect();
+    recording.0.frames.push(ReplayFrame {
+        delta: time.delta(),
+        controls: ControlBits::of(&action_state),
+        players,
+    });
+}
+
+/// Overwrites what the devices did with the replay.
+/// Pausing is up to whoever watches, the pauses of the recording are left out. While paused, the
+/// devices work the pause menu.
+fn play_back_input(
+    playback: Res<Playback>,
+    pause_state: Option<Res<State<PauseState>>>,
+    mut action_state: ResMut<ActionState>,
+    mut player_query: Query<(&Player, &mut PlayerDevices, &mut ActionState)>,
+) {
+    let Some(frame) = playback.replay.frames.get(playback.frame) else {
+        return;
+    };
+    if pause_state.is_some_and(|state| *state.get() == PauseState::Running) {
+        frame
+            .controls
+            .keep(GameControl::Pause, &action_state)
+            .apply(&mut action_state);
+    }
+    for (player, mut devices, mut player_action_state) in player_query.iter_mut() {
+        *devices = PlayerDevices::Replay;
+        let Some(player_frame) = frame.players.iter().find(|p| p.id as usize == player.id) else {
+            continue;
+        };
+        player_frame.controls.apply(&mut player_action_state);
+        player_action_state.movement = player_frame.movement;
+        player_action_state.move_target = player_frame.move_target;
+        player_action_state.aim_direction = player_frame.aim_direction;
+        player_action_state.aim_target = player_frame.aim_target;
+    }
+}
+
+/// Players that joined during the recording join at the same time
+fn play_back_joins(
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    fonts: Res<Fonts>,
+    playback: Res<Playback>,
+    player_query: Query<(&Player, &Transform, Has<Health>)>,
+) {
+    let Some(frame) = playback.replay.frames.get(playback.frame) else {
+        return;
+    };
+    for player_frame in &frame.players {
+        let id = player_frame.id as usize;
+        if player_query.iter().any(|(player, ..)| player.id == id) {
+            continue;
+        }
+        let position = join_position(
+            player_query
+                .iter()
+                .map(|(_, transform, alive)| (transform, alive)),
+            id,
+        );
+        spawn_player(
+            &mut commands,
+            &textures,
+            &fonts,
+            id,
+            PlayerDevices::Replay,
+            position,
+        );
+    }
+}
+
+fn advance_playback(
+    mut commands: Commands,
+    mut playback: ResMut<Playback>,
+    mut time_update_strategy: ResMut<TimeUpdateStrategy>,
+) {
+    playback.frame += 1;
+    if let Some(frame) = playback.replay.frames.get(playback.frame) {
+        *time_update_strategy = TimeUpdateStrategy::ManualDuration(frame.delta);
+    } else {
+        info!("Replay finished");
+        commands.remove_resource::<Playback>();
+        *time_update_strategy = TimeUpdateStrategy::Automatic;
+    }
+}
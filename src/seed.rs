@@ -0,0 +1,116 @@
+//! Everything random in a run derives from one seed, so runs can be shared and replayed.
+//! The seed is random unless it's given on the command line, in the environment or is the one
+//! of the daily challenge.
+
+use bevy::prelude::*;
+use bevy::utils::{HashMap, SystemTime};
+use bevy_rand::prelude::WyRand;
+use rand_core::SeedableRng;
+
+/// Overrides the seed like `--seed`, if that is not given
+pub const SEED_ENV_VAR: &str = "BLADE_SOUL_SEED";
+
+/// How the seed of new runs is chosen, when it's not random
+#[derive(Clone, Debug, PartialEq)]
+pub enum SeedOption {
+    Fixed(u64),
+    /// The same for everyone on the same (UTC) day
+    Daily,
+}
+
+impl SeedOption {
+    /// `daily`, a number, or any text which is hashed into a seed.
+    /// The daily seed of a past day can be given as its date, e.g. `2025-01-31`.
+    pub fn parse(value: &str) -> Self {
+        if value.eq_ignore_ascii_case("daily") {
+            return SeedOption::Daily;
+        }
+        SeedOption::Fixed(value.parse().unwrap_or_else(|_| hash(&[value.as_bytes()])))
+    }
+}
+
+/// The seed of the current (or last) run
+#[derive(Resource, Default, Clone, Debug)]
+pub struct RunSeed {
+    pub seed: u64,
+    /// The date, if this is a daily challenge
+    pub daily: Option<String>,
+}
+
+impl RunSeed {
+    pub fn from_option(option: &SeedOption) -> Self {
+        match option {
+            SeedOption::Fixed(seed) => Self {
+                seed: *seed,
+                daily: None,
+            },
+            SeedOption::Daily => {
+                let date = today();
+                Self {
+                    seed: hash(&[date.as_bytes()]),
+                    daily: Some(date),
+                }
+            }
+        }
+    }
+}
+
+/// The random numbers of gameplay, seeded from the [`RunSeed`] when a run starts.
+/// Don't use the global entropy for gameplay, it wouldn't replay the same.
+///
+/// Every consumer draws from its own named stream. That way, adding a new one doesn't change
+/// the numbers the others get for the same seed.
+#[derive(Resource)]
+pub struct RunRng {
+    seed: u64,
+    streams: HashMap<&'static str, WyRand>,
+}
+
+impl RunRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            streams: default(),
+        }
+    }
+
+    pub fn stream(&mut self, name: &'static str) -> &mut WyRand {
+        let seed = self.seed;
+        self.streams
+            .entry(name)
+            .or_insert_with(|| WyRand::seed_from_u64(hash(&[&seed.to_le_bytes(), name.as_bytes()])))
+    }
+}
+
+/// FNV-1a, unlike the std hashers it's guaranteed to stay the same across Rust versions
+fn hash(parts: &[&[u8]]) -> u64 {
+    parts
+        .iter()
+        .flat_map(|part| part.iter())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// The current UTC date as `YYYY-MM-DD`
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs() / 86400) as i64;
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -0,0 +1,368 @@
+use bevy::prelude::*;
+use bevy::ui::FocusPolicy;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use bevy::window::{PrimaryWindow, WindowMode};
+use serde::{Deserialize, Serialize};
+
+use crate::loading::Fonts;
+use crate::menu::{click_change_state, spawn_button, ButtonColors, ChangeState};
+use crate::storage::Storage;
+use crate::{GameState, PauseState};
+
+/// Bump this when the meaning of a stored setting changes and migrate in [`Settings::migrate`]
+pub const SETTINGS_VERSION: u32 = 1;
+
+pub struct SettingsPlugin;
+
+/// This plugin loads the user settings at startup and provides the settings screen.
+/// The screen is an overlay, so it can be opened from the main menu as well as from the pause menu.
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = Settings::load(app.world().resource::<Storage>());
+        app.insert_resource(settings)
+            .init_state::<SettingsState>()
+            .enable_state_scoped_entities::<SettingsState>()
+            .add_systems(OnEnter(SettingsState::Open), setup_settings_menu)
+            .add_systems(OnExit(SettingsState::Open), save_settings)
+            .add_systems(OnExit(GameState::Menu), close_settings)
+            .add_systems(OnExit(PauseState::Paused), close_settings)
+            .add_systems(
+                Update,
+                (
+                    click_change_state::<SettingsState>,
+                    click_setting_buttons,
+                    update_setting_values,
+                ),
+            );
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        app.add_systems(
+            Update,
+            apply_window_mode.run_if(resource_changed::<Settings>),
+        );
+    }
+}
+
+#[derive(States, Default, Clone, Copy, Eq, PartialEq, Debug, Hash)]
+pub enum SettingsState {
+    #[default]
+    Closed,
+    Open,
+    Controls,
+}
+
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub window_mode: WindowModeSetting,
+    /// Screen pixels per world pixel on a 1280x720 window. Other windows show the same world area.
+    pub camera_zoom: f32,
+    pub screen_shake: bool,
+    pub aim_mode: AimMode,
+    /// Health bars over enemies and floating damage numbers
+    pub damage_feedback: bool,
+}
+
+/// Where attacks are aimed
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AimMode {
+    /// With the mouse or the right stick, falls back to the movement direction
+    #[default]
+    Independent,
+    /// Always in the movement direction
+    Movement,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowModeSetting {
+    #[default]
+    Windowed,
+    Fullscreen,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            master_volume: 1.0,
+            music_volume: 0.8,
+            sfx_volume: 1.0,
+            window_mode: default(),
+            camera_zoom: 4.0,
+            screen_shake: true,
+            aim_mode: default(),
+            damage_feedback: true,
+        }
+    }
+}
+
+impl Settings {
+    const ZOOM_LEVELS: [f32; 5] = [2.0, 3.0, 4.0, 5.0, 6.0];
+
+    fn load(storage: &Storage) -> Self {
+        let Some(settings) = storage.load::<Settings>("settings") else {
+            return default();
+        };
+        if settings.version > SETTINGS_VERSION {
+            warn!(
+                "Settings were saved by a newer version ({}), using defaults",
+                settings.version
+            );
+            return default();
+        }
+        settings.migrate()
+    }
+
+    /// Upgrades settings stored by older versions. Fields added since then are already defaulted.
+    fn migrate(self) -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            ..self
+        }
+    }
+
+    /// Effective volume of sound effects
+    pub fn sfx_volume(&self) -> f64 {
+        (self.master_volume * self.sfx_volume) as f64
+    }
+
+    /// Effective volume of the music
+    pub fn music_volume(&self) -> f64 {
+        (self.master_volume * self.music_volume) as f64
+    }
+
+    fn change(&mut self, kind: SettingKind, step: i32) {
+        let volume = |volume: f32| (volume + step as f32 * 0.1).clamp(0.0, 1.0);
+        match kind {
+            SettingKind::MasterVolume => self.master_volume = volume(self.master_volume),
+            SettingKind::MusicVolume => self.music_volume = volume(self.music_volume),
+            SettingKind::SfxVolume => self.sfx_volume = volume(self.sfx_volume),
+            SettingKind::WindowMode => {
+                self.window_mode = match self.window_mode {
+                    WindowModeSetting::Windowed => WindowModeSetting::Fullscreen,
+                    WindowModeSetting::Fullscreen => WindowModeSetting::Windowed,
+                }
+            }
+            SettingKind::CameraZoom => {
+                let current = Self::ZOOM_LEVELS
+                    .iter()
+                    .position(|zoom| *zoom >= self.camera_zoom)
+                    .unwrap_or(Self::ZOOM_LEVELS.len() - 1) as i32;
+                let next = (current + step).clamp(0, Self::ZOOM_LEVELS.len() as i32 - 1);
+                self.camera_zoom = Self::ZOOM_LEVELS[next as usize];
+            }
+            SettingKind::ScreenShake => self.screen_shake = !self.screen_shake,
+            SettingKind::DamageFeedback => self.damage_feedback = !self.damage_feedback,
+            SettingKind::AimMode => {
+                self.aim_mode = match self.aim_mode {
+                    AimMode::Independent => AimMode::Movement,
+                    AimMode::Movement => AimMode::Independent,
+                }
+            }
+        }
+    }
+
+    fn display(&self, kind: SettingKind) -> String {
+        let on_off = |value: bool| if value { "On" } else { "Off" }.to_string();
+        match kind {
+            SettingKind::MasterVolume => format!("{:.0}%", self.master_volume * 100.0),
+            SettingKind::MusicVolume => format!("{:.0}%", self.music_volume * 100.0),
+            SettingKind::SfxVolume => format!("{:.0}%", self.sfx_volume * 100.0),
+            SettingKind::WindowMode => format!("{:?}", self.window_mode),
+            SettingKind::CameraZoom => format!("{}x", self.camera_zoom),
+            SettingKind::ScreenShake => on_off(self.screen_shake),
+            SettingKind::DamageFeedback => on_off(self.damage_feedback),
+            SettingKind::AimMode => format!("{:?}", self.aim_mode),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SettingKind {
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+    WindowMode,
+    CameraZoom,
+    ScreenShake,
+    AimMode,
+    DamageFeedback,
+}
+
+/// Changes the setting by `step` when pressed, toggles ignore the step
+#[derive(Component)]
+struct SettingButton {
+    kind: SettingKind,
+    step: i32,
+}
+
+/// Displays the current value of a setting
+#[derive(Component)]
+struct SettingValue(SettingKind);
+
+fn setup_settings_menu(mut commands: Commands, fonts: Res<Fonts>) {
+    let text_font = TextFont {
+        font: fonts.font.clone(),
+        font_size: 32.0,
+        ..default()
+    };
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(Color::linear_rgba(0.0, 0.0, 0.0, 0.85)),
+            // Clicks next to the buttons don't reach the menu underneath
+            FocusPolicy::Block,
+            GlobalZIndex(2),
+            StateScoped(SettingsState::Open),
+        ))
+        .with_children(|children| {
+            children.spawn((
+                Text::new("Settings"),
+                TextFont {
+                    font_size: 64.0,
+                    ..text_font.clone()
+                },
+            ));
+            for (label, kind) in [
+                ("Master volume", SettingKind::MasterVolume),
+                ("Music volume", SettingKind::MusicVolume),
+                ("Effects volume", SettingKind::SfxVolume),
+                ("Window", SettingKind::WindowMode),
+                ("Zoom", SettingKind::CameraZoom),
+                ("Screen shake", SettingKind::ScreenShake),
+                ("Aim", SettingKind::AimMode),
+                ("Damage display", SettingKind::DamageFeedback),
+            ] {
+                spawn_setting_row(children, label, kind, &text_font);
+            }
+            spawn_button(children, "Controls", ChangeState(SettingsState::Controls));
+            spawn_button(children, "Back", ChangeState(SettingsState::Closed));
+        });
+}
+
+fn spawn_setting_row(parent: &mut ChildBuilder, label: &str, kind: SettingKind, font: &TextFont) {
+    let is_toggle = matches!(
+        kind,
+        SettingKind::WindowMode
+            | SettingKind::ScreenShake
+            | SettingKind::AimMode
+            | SettingKind::DamageFeedback
+    );
+    parent
+        .spawn(Node {
+            width: Val::Px(520.0),
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Px(4.0)),
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn((
+                Text::new(label),
+                font.clone(),
+                Node {
+                    flex_grow: 1.0,
+                    ..default()
+                },
+            ));
+            if !is_toggle {
+                spawn_small_button(row, "-", SettingButton { kind, step: -1 }, font);
+            }
+            row.spawn((
+                Text::default(),
+                font.clone(),
+                Node {
+                    width: Val::Px(140.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                TextLayout::new_with_justify(JustifyText::Center),
+                SettingValue(kind),
+            ));
+            if is_toggle {
+                spawn_small_button(row, "<>", SettingButton { kind, step: 1 }, font);
+            } else {
+                spawn_small_button(row, "+", SettingButton { kind, step: 1 }, font);
+            }
+        });
+}
+
+fn spawn_small_button(
+    parent: &mut ChildBuilder,
+    label: &str,
+    action: impl Bundle,
+    font: &TextFont,
+) {
+    let button_colors = ButtonColors::default();
+    parent
+        .spawn((
+            Button,
+            Node {
+                width: Val::Px(40.0),
+                height: Val::Px(40.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(button_colors.normal),
+            button_colors,
+            action,
+        ))
+        .with_child((Text::new(label), font.clone()));
+}
+
+fn click_setting_buttons(
+    mut settings: ResMut<Settings>,
+    interaction_query: Query<(&Interaction, &SettingButton), (Changed<Interaction>, With<Button>)>,
+) {
+    for (interaction, button) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            settings.change(button.kind, button.step);
+        }
+    }
+}
+
+fn update_setting_values(
+    settings: Res<Settings>,
+    mut value_query: Query<(Ref<SettingValue>, &mut Text)>,
+) {
+    for (value, mut text) in value_query.iter_mut() {
+        if settings.is_changed() || value.is_added() {
+            text.0 = settings.display(value.0);
+        }
+    }
+}
+
+fn save_settings(settings: Res<Settings>, storage: Res<Storage>) {
+    storage.save("settings", &*settings);
+}
+
+fn close_settings(mut next_state: ResMut<NextState<SettingsState>>) {
+    next_state.set(SettingsState::Closed);
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn apply_window_mode(
+    settings: Res<Settings>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let Ok(mut window) = window_query.get_single_mut() else {
+        return;
+    };
+    window.mode = match settings.window_mode {
+        WindowModeSetting::Windowed => WindowMode::Windowed,
+        WindowModeSetting::Fullscreen => {
+            WindowMode::BorderlessFullscreen(MonitorSelection::Current)
+        }
+    };
+}
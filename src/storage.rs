@@ -0,0 +1,199 @@
+//! Persists small RON documents, like the settings, and binary blobs, like replays.
+//! On desktop they are files in the user's config directory, on mobile in the app's data
+//! directory and on the web they go to local storage.
+
+use bevy::log::warn;
+use bevy::prelude::Resource;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const APP_NAME: &str = "blade_soul";
+
+/// Where the game keeps its data. Headless runs use [`Storage::Disabled`], so they never touch
+/// the player's data.
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub enum Storage {
+    Persistent,
+    /// Loading and saving are no-ops
+    Disabled,
+}
+
+impl Storage {
+    /// Loads the document `name`, returns `None` if it was never saved or can't be read
+    pub fn load<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        if *self == Storage::Disabled {
+            return None;
+        }
+        let content = read(name)?;
+        ron::from_str(&content)
+            .inspect_err(|e| warn!("Could not parse stored {name}: {e}"))
+            .ok()
+    }
+
+    /// Stores the document `name`, failures are only logged
+    pub fn save<T: Serialize>(&self, name: &str, value: &T) {
+        if *self == Storage::Disabled {
+            return;
+        }
+        match ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default()) {
+            Ok(content) => write(name, &content),
+            Err(e) => warn!("Could not serialize {name}: {e}"),
+        }
+    }
+
+    /// Loads the blob `name`, returns `None` if it was never saved or can't be read
+    pub fn load_bytes(&self, name: &str) -> Option<Vec<u8>> {
+        if *self == Storage::Disabled {
+            return None;
+        }
+        read_bytes(name)
+    }
+
+    /// Stores the blob `name`, failures are only logged
+    pub fn save_bytes(&self, name: &str, bytes: &[u8]) {
+        if *self == Storage::Disabled {
+            return;
+        }
+        write_bytes(name, bytes);
+    }
+
+    /// Removes the blob `name`, if it was saved
+    pub fn remove_bytes(&self, name: &str) {
+        if *self == Storage::Disabled {
+            return;
+        }
+        remove_bytes(name);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path(file_name: &str) -> Option<std::path::PathBuf> {
+    Some(base_dir()?.join(APP_NAME).join(file_name))
+}
+
+#[cfg(target_os = "android")]
+fn base_dir() -> Option<std::path::PathBuf> {
+    bevy::window::ANDROID_APP.get()?.internal_data_path()
+}
+
+/// The app's sandbox, it keeps this across updates
+#[cfg(target_os = "ios")]
+fn base_dir() -> Option<std::path::PathBuf> {
+    let home = std::path::PathBuf::from(std::env::var_os("HOME")?);
+    Some(home.join("Library").join("Application Support"))
+}
+
+#[cfg(not(any(target_arch = "wasm32", target_os = "android", target_os = "ios")))]
+fn base_dir() -> Option<std::path::PathBuf> {
+    dirs::config_dir()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read(name: &str) -> Option<String> {
+    std::fs::read_to_string(path(&format!("{name}.ron"))?).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write(name: &str, content: &str) {
+    write_file(name, &format!("{name}.ron"), content.as_bytes());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_bytes(name: &str) -> Option<Vec<u8>> {
+    std::fs::read(path(&format!("{name}.bin"))?).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_bytes(name: &str, content: &[u8]) {
+    write_file(name, &format!("{name}.bin"), content);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn remove_bytes(name: &str) {
+    let Some(path) = path(&format!("{name}.bin")) else {
+        return;
+    };
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            warn!("Could not remove {}: {e}", path.display());
+        }
+        _ => {}
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_file(name: &str, file_name: &str, content: &[u8]) {
+    let Some(path) = path(file_name) else {
+        warn!("No config directory available, {name} will not be saved");
+        return;
+    };
+    if let Some(parent) = path.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent) {
+            warn!("Could not create {}: {e}", parent.display());
+            return;
+        }
+    }
+    if let Err(e) = std::fs::write(&path, content) {
+        warn!("Could not write {}: {e}", path.display());
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read(name: &str) -> Option<String> {
+    local_storage()?
+        .get_item(&format!("{APP_NAME}.{name}"))
+        .ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write(name: &str, content: &str) {
+    let Some(storage) = local_storage() else {
+        warn!("No local storage available, {name} will not be saved");
+        return;
+    };
+    if storage
+        .set_item(&format!("{APP_NAME}.{name}"), content)
+        .is_err()
+    {
+        warn!("Could not write {name} to local storage");
+    }
+}
+
+// Local storage only holds strings, blobs are stored base64 encoded
+
+#[cfg(target_arch = "wasm32")]
+fn read_bytes(name: &str) -> Option<Vec<u8>> {
+    use base64::prelude::*;
+    BASE64_STANDARD.decode(read(name)?).ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_bytes(name: &str, content: &[u8]) {
+    use base64::prelude::*;
+    let Some(storage) = local_storage() else {
+        warn!("No local storage available, {name} will not be saved");
+        return;
+    };
+    let key = format!("{APP_NAME}.{name}");
+    if storage
+        .set_item(&key, &BASE64_STANDARD.encode(content))
+        .is_err()
+    {
+        // Most likely the quota is used up. The outdated blob goes as well, so it doesn't take the
+        // room of the documents.
+        warn!("Could not write {name} to local storage, it is probably full");
+        remove_bytes(name);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn remove_bytes(name: &str) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.remove_item(&format!("{APP_NAME}.{name}"));
+    }
+}
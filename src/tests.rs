@@ -0,0 +1,663 @@
+//! Runs the game headless and checks combat and AI by stepping frames.
+
+use avian2d::prelude::Position;
+use bevy::ecs::system::RunSystemOnce;
+use bevy::input::InputPlugin;
+use bevy::math::vec2;
+use bevy::prelude::*;
+use bevy::render::mesh::MeshPlugin;
+use bevy::scene::ScenePlugin;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy::window::ExitCondition;
+use std::time::Duration;
+
+use crate::actions::{
+    Actions, GameControl, Health, InputBindings, StatusEffect, StatusEffects, StatusKind,
+};
+use crate::audio::{bar_started, has_room, next_track, NextTrack};
+use crate::camera::MapBounds;
+use crate::enemies::{spawn_melee_enemy, Ai, EnemySpawnSet};
+use crate::game_over::RunStats;
+use crate::high_scores::HighScores;
+use crate::intensity::CombatIntensity;
+use crate::loading::TextureAssets;
+use crate::pickups::{spawn_pickup, Pickup};
+use crate::player::{Player, PlayerForm};
+use crate::progression::{ChooseUpgrade, Experience, UpgradeOffer};
+use crate::replay::WatchLastRun;
+use crate::seed::SeedOption;
+use crate::settings::Settings;
+use crate::{GamePlugin, GameState, LaunchOptions, PauseState};
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// A running game without enemy spawns, with one player at the origin
+fn playing_app() -> App {
+    playing_app_with(LaunchOptions::default(), false)
+}
+
+/// With `enemy_spawns`, enemies keep spawning like in a real run
+fn playing_app_with(launch_options: LaunchOptions, enemy_spawns: bool) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        ScenePlugin,
+        MeshPlugin,
+        StatesPlugin,
+        InputPlugin,
+        WindowPlugin {
+            primary_window: None,
+            exit_condition: ExitCondition::DontExit,
+            ..default()
+        },
+        TransformPlugin,
+        HierarchyPlugin,
+        GamePlugin { headless: true },
+    ))
+    .insert_resource(launch_options)
+    .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME))
+    .configure_sets(FixedUpdate, EnemySpawnSet.run_if(move || enemy_spawns));
+    app.update();
+    app.world_mut()
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::Playing);
+    app.update();
+    app
+}
+
+fn step(app: &mut App, frames: usize) {
+    for _ in 0..frames {
+        app.update();
+    }
+}
+
+fn player(app: &mut App) -> Entity {
+    app.world_mut()
+        .query_filtered::<Entity, With<Player>>()
+        .single(app.world())
+}
+
+fn spawn_enemy(app: &mut App, position: Vec2) -> Entity {
+    app.world_mut()
+        .run_system_once(
+            move |mut commands: Commands, textures: Res<TextureAssets>| {
+                spawn_melee_enemy(&mut commands, &textures, position)
+            },
+        )
+        .expect("Could not spawn enemy")
+}
+
+fn keys(app: &mut App) -> Mut<'_, ButtonInput<KeyCode>> {
+    app.world_mut().resource_mut::<ButtonInput<KeyCode>>()
+}
+
+fn position(app: &App, entity: Entity) -> Vec2 {
+    app.world()
+        .get::<Transform>(entity)
+        .expect("Entity has no transform")
+        .translation
+        .xy()
+}
+
+/// Holds the attack for `frames`, aiming right, then lets go
+fn attack(app: &mut App, frames: usize) {
+    keys(app).press(KeyCode::KeyD);
+    keys(app).press(KeyCode::Space);
+    step(app, frames);
+    keys(app).release(KeyCode::Space);
+    keys(app).release(KeyCode::KeyD);
+    app.update();
+}
+
+#[test]
+fn player_spawns_with_full_health() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+
+    let health = app
+        .world()
+        .get::<Health>(player)
+        .expect("Player has no health");
+    assert_eq!(health.health, 5);
+    assert_eq!(health.max_health, 5);
+}
+
+#[test]
+fn attack_charges_executes_and_cools_down() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+
+    keys(&mut app).press(KeyCode::KeyD);
+    keys(&mut app).press(KeyCode::Space);
+    step(&mut app, 10);
+    assert!(matches!(
+        app.world().get::<Actions>(player),
+        Some(Actions::Charging { .. })
+    ));
+
+    keys(&mut app).release(KeyCode::Space);
+    keys(&mut app).release(KeyCode::KeyD);
+    app.update();
+    assert!(matches!(
+        app.world().get::<Actions>(player),
+        Some(Actions::Executing { .. } | Actions::Cooldown(_))
+    ));
+
+    step(&mut app, 5);
+    assert!(matches!(
+        app.world().get::<Actions>(player),
+        Some(Actions::Cooldown(_))
+    ));
+
+    step(&mut app, 70);
+    assert!(matches!(
+        app.world().get::<Actions>(player),
+        Some(Actions::Idle)
+    ));
+}
+
+#[test]
+fn attack_kills_adjacent_enemy() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    let enemy = spawn_enemy(&mut app, vec2(10.0, 0.0));
+
+    attack(&mut app, 1);
+    step(&mut app, 10);
+
+    assert!(app.world().get::<Health>(enemy).is_none());
+    assert_eq!(app.world().get::<Player>(player).unwrap().score, 1);
+    assert_eq!(app.world().resource::<RunStats>().kills, 1);
+
+    // The death animation plays before the enemy disappears
+    step(&mut app, 70);
+    assert!(!app.world().entities().contains(enemy));
+}
+
+#[test]
+fn melee_enemy_damages_player() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    spawn_enemy(&mut app, vec2(10.0, 0.0));
+
+    step(&mut app, 120);
+
+    let health = app
+        .world()
+        .get::<Health>(player)
+        .expect("Player has no health");
+    assert!(health.health < 5);
+}
+
+#[test]
+fn enemy_chases_player() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    let enemy = spawn_enemy(&mut app, vec2(100.0, 0.0));
+    app.update();
+    let start_distance = position(&app, enemy).distance(position(&app, player));
+
+    step(&mut app, 90);
+
+    let distance = position(&app, enemy).distance(position(&app, player));
+    assert!(
+        distance < start_distance,
+        "Enemy did not come closer: {start_distance} -> {distance}"
+    );
+}
+
+#[test]
+fn last_player_dying_ends_the_run() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    app.world_mut()
+        .get_mut::<Health>(player)
+        .expect("Player has no health")
+        .health = 1;
+    spawn_enemy(&mut app, vec2(10.0, 0.0));
+
+    for _ in 0..600 {
+        app.update();
+        if *app.world().resource::<State<GameState>>().get() == GameState::GameOver {
+            let high_scores = app.world().resource::<HighScores>();
+            assert_eq!(high_scores.entries.len(), 1);
+            assert!(high_scores.new_record());
+            return;
+        }
+    }
+    panic!("The run did not end");
+}
+
+#[test]
+fn same_seed_spawns_same_enemies() {
+    let enemy_positions = || {
+        let mut app = playing_app_with(
+            LaunchOptions {
+                seed: Some(SeedOption::Fixed(42)),
+                ..default()
+            },
+            true,
+        );
+        step(&mut app, 300);
+        app.world_mut()
+            .query_filtered::<&Transform, With<Ai>>()
+            .iter(app.world())
+            .map(|transform| transform.translation.xy())
+            .collect::<Vec<_>>()
+    };
+
+    let first = enemy_positions();
+    assert!(!first.is_empty());
+    assert_eq!(first, enemy_positions());
+}
+
+#[test]
+fn short_presses_are_not_lost_between_ticks() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    // Faster than the gameplay ticks, so some frames have none
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_nanos(
+        1_000_000_000 / 144,
+    )));
+
+    for _ in 0..3 {
+        keys(&mut app).press(KeyCode::KeyQ);
+        app.update();
+        keys(&mut app).release(KeyCode::KeyQ);
+        step(&mut app, 10);
+    }
+
+    let form = app.world().get::<Player>(player).unwrap().form;
+    assert!(matches!(form, PlayerForm::Bow));
+}
+
+/// What a character is up to, with the timers of its attack
+fn describe(actions: &Actions) -> String {
+    match actions {
+        Actions::Idle => "idle".to_string(),
+        Actions::Cooldown(timer) => format!("cooldown {:?}", timer.elapsed()),
+        Actions::Charging {
+            trigger_direction, ..
+        } => format!("charging {trigger_direction:?}"),
+        Actions::Executing {
+            trigger_direction,
+            pending_cooldown,
+            steps,
+        } => format!(
+            "executing {trigger_direction:?} {:?} {:?}",
+            pending_cooldown.elapsed(),
+            steps
+                .iter()
+                .map(|step| step.timer.elapsed())
+                .collect::<Vec<_>>()
+        ),
+        Actions::ChangePlayerForm(form) => format!("changing to {form:?}"),
+    }
+}
+
+/// Plays the same seeded fight at `fps` and tells where everyone is and what they're doing
+/// after each input
+fn fight_at(fps: u32) -> Vec<Vec<(Vec2, u32, String)>> {
+    let mut app = playing_app_with(
+        LaunchOptions {
+            seed: Some(SeedOption::Fixed(42)),
+            ..default()
+        },
+        true,
+    );
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_nanos(
+        1_000_000_000 / fps as u64,
+    )));
+    spawn_enemy(&mut app, vec2(60.0, 0.0));
+    spawn_enemy(&mut app, vec2(-40.0, 50.0));
+
+    // Keys held and for how many sixths of a second. Frames at every frame rate start on those,
+    // between gameplay ticks.
+    let inputs: [(&[KeyCode], u32); 9] = [
+        (&[KeyCode::KeyD], 2),
+        (&[KeyCode::KeyD, KeyCode::Space], 3),
+        (&[], 2),
+        (&[KeyCode::KeyQ], 1),
+        (&[KeyCode::KeyW, KeyCode::Space], 4),
+        (&[], 3),
+        (&[KeyCode::KeyA, KeyCode::KeyS], 2),
+        (&[KeyCode::Space], 1),
+        (&[], 6),
+    ];
+    inputs
+        .into_iter()
+        .map(|(pressed, sixths)| {
+            for key in pressed {
+                keys(&mut app).press(*key);
+            }
+            step(&mut app, (sixths * fps / 6) as usize);
+            keys(&mut app).release_all();
+
+            let mut characters = app
+                .world_mut()
+                .query::<(&Position, &Health, &Actions)>()
+                .iter(app.world())
+                .map(|(position, health, actions)| (position.0, health.health, describe(actions)))
+                .collect::<Vec<_>>();
+            characters.sort_by(|a, b| a.0.x.total_cmp(&b.0.x).then(a.0.y.total_cmp(&b.0.y)));
+            characters
+        })
+        .collect()
+}
+
+#[test]
+fn gameplay_is_the_same_at_any_frame_rate() {
+    let at_60 = fight_at(60);
+    assert_eq!(fight_at(30), at_60);
+    assert_eq!(fight_at(144), at_60);
+}
+
+#[test]
+fn burn_damages_until_it_runs_out() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    app.world_mut()
+        .get_mut::<StatusEffects>(player)
+        .expect("Player has no status effects")
+        .apply(StatusEffect::new(StatusKind::Burn, 2.5), player);
+
+    step(&mut app, 200);
+
+    let health = app
+        .world()
+        .get::<Health>(player)
+        .expect("Player has no health");
+    assert_eq!(health.health, 3);
+}
+
+#[test]
+fn stunned_enemy_does_not_move() {
+    let mut app = playing_app();
+    let enemy = spawn_enemy(&mut app, vec2(100.0, 0.0));
+    app.update();
+    app.world_mut()
+        .get_mut::<StatusEffects>(enemy)
+        .expect("Enemy has no status effects")
+        .apply(StatusEffect::new(StatusKind::Stun, 2.0), enemy);
+    let start = position(&app, enemy);
+
+    step(&mut app, 90);
+
+    assert!(position(&app, enemy).distance(start) < 0.1);
+}
+
+#[test]
+fn collected_xp_levels_up_and_offers_upgrades() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    app.world_mut()
+        .get_mut::<Experience>(player)
+        .expect("Player has no experience")
+        .xp = 2;
+    spawn_enemy(&mut app, vec2(10.0, 0.0));
+
+    attack(&mut app, 1);
+    step(&mut app, 30);
+
+    assert_eq!(
+        *app.world().resource::<State<PauseState>>().get(),
+        PauseState::LevelUp
+    );
+    assert_eq!(app.world().resource::<UpgradeOffer>().choices.len(), 3);
+
+    let button = app
+        .world_mut()
+        .query_filtered::<Entity, With<ChooseUpgrade>>()
+        .iter(app.world())
+        .next()
+        .expect("No upgrade to choose");
+    app.world_mut()
+        .entity_mut(button)
+        .insert(Interaction::Pressed);
+    step(&mut app, 2);
+
+    assert_eq!(
+        *app.world().resource::<State<PauseState>>().get(),
+        PauseState::Running
+    );
+    let experience = app.world().get::<Experience>(player).unwrap();
+    assert_eq!(experience.level, 2);
+    assert_eq!(experience.pending_level_ups, 0);
+}
+
+#[test]
+fn heal_pickup_is_attracted_and_heals() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    app.world_mut()
+        .get_mut::<Health>(player)
+        .expect("Player has no health")
+        .health = 3;
+    let pickup = app
+        .world_mut()
+        .run_system_once(|mut commands: Commands| {
+            spawn_pickup(&mut commands, Pickup::Heal(1), vec2(30.0, 0.0))
+        })
+        .expect("Could not spawn pickup");
+
+    step(&mut app, 60);
+
+    assert!(!app.world().entities().contains(pickup));
+    assert_eq!(app.world().get::<Health>(player).unwrap().health, 4);
+}
+
+#[test]
+fn bow_shoots_distant_enemy() {
+    let mut app = playing_app();
+    let player = player(&mut app);
+    keys(&mut app).press(KeyCode::KeyQ);
+    app.update();
+    keys(&mut app).release(KeyCode::KeyQ);
+    step(&mut app, 5);
+    let enemy = spawn_enemy(&mut app, vec2(80.0, 0.0));
+
+    attack(&mut app, 1);
+    step(&mut app, 30);
+
+    assert!(app.world().get::<Health>(enemy).is_none());
+    assert_eq!(app.world().get::<Player>(player).unwrap().score, 1);
+}
+
+#[test]
+fn camera_view_stays_inside_isometric_map() {
+    let bounds = MapBounds {
+        center: Vec2::ZERO,
+        half_size: vec2(200.0, 100.0),
+        diamond: true,
+    };
+    let half_view = vec2(40.0, 20.0);
+
+    assert_eq!(bounds.clamp(vec2(10.0, 5.0), half_view), vec2(10.0, 5.0));
+    let clamped = bounds.clamp(vec2(300.0, 80.0), half_view);
+    for corner in [half_view, -half_view, half_view * vec2(1.0, -1.0)] {
+        let corner = (clamped + corner).abs() / bounds.half_size;
+        assert!(
+            corner.element_sum() <= 1.0 + 1e-4,
+            "View corner outside: {corner}"
+        );
+    }
+    // Too big to fit, looking at the middle
+    assert_eq!(bounds.clamp(vec2(50.0, 0.0), vec2(150.0, 80.0)), Vec2::ZERO);
+}
+
+#[test]
+fn camera_zooms_out_smoothly_to_frame_nearby_enemy() {
+    let mut app = playing_app();
+    // Without a window the view is the reference one, 80 by 45 world units at this zoom
+    app.world_mut().resource_mut::<Settings>().camera_zoom = 16.0;
+    let enemy = spawn_enemy(&mut app, vec2(60.0, 0.0));
+    app.update();
+    app.world_mut()
+        .get_mut::<StatusEffects>(enemy)
+        .expect("Enemy has no status effects")
+        .apply(StatusEffect::new(StatusKind::Stun, 5.0), enemy);
+    let camera_scale = |app: &mut App| {
+        app.world_mut()
+            .query::<&OrthographicProjection>()
+            .single(app.world())
+            .scale
+    };
+
+    step(&mut app, 5);
+    let zooming = camera_scale(&mut app);
+    step(&mut app, 120);
+    let framed = camera_scale(&mut app);
+
+    assert!(
+        zooming > 1.0 && zooming < framed - 0.1,
+        "Zoom jumped to {zooming}"
+    );
+    // The span of player and enemy plus the margins, over the view width
+    assert!((framed - 140.0 / 80.0).abs() < 0.05, "Framed at {framed}");
+}
+
+#[test]
+fn combat_intensity_rises_with_enemies_around() {
+    let mut app = playing_app();
+    step(&mut app, 30);
+    assert_eq!(app.world().resource::<CombatIntensity>().value, 0.0);
+
+    for angle in [0.0, 1.5, 3.0, 4.5] {
+        spawn_enemy(&mut app, Vec2::from_angle(angle) * 60.0);
+    }
+    step(&mut app, 60);
+
+    let intensity = app.world().resource::<CombatIntensity>().value;
+    assert!(intensity > 0.1, "Intensity only {intensity}");
+}
+
+#[test]
+fn rebinding_a_slot_keeps_the_other_bindings() {
+    let mut bindings = InputBindings::default();
+    bindings.bind_key(GameControl::Up, 1, KeyCode::KeyI);
+    assert_eq!(
+        bindings.keys(GameControl::Up),
+        [KeyCode::KeyW, KeyCode::KeyI]
+    );
+    // Bound in the other slot already, they swap
+    bindings.bind_key(GameControl::Up, 0, KeyCode::KeyI);
+    assert_eq!(
+        bindings.keys(GameControl::Up),
+        [KeyCode::KeyI, KeyCode::KeyW]
+    );
+    // An empty slot adds the binding
+    bindings.bind_key(GameControl::Attack, 1, KeyCode::KeyJ);
+    assert_eq!(
+        bindings.keys(GameControl::Attack),
+        [KeyCode::Space, KeyCode::KeyJ]
+    );
+    bindings.bind_button(GameControl::Attack, 0, GamepadButton::West);
+    assert_eq!(bindings.buttons(GameControl::Attack), [GamepadButton::West]);
+}
+
+#[test]
+fn sound_limit_frees_up_when_sounds_stop() {
+    let mut playing = vec![1, 2, 3];
+    assert!(!has_room(&mut playing, 3, |_| false));
+    assert_eq!(playing, [1, 2, 3]);
+
+    assert!(has_room(&mut playing, 3, |instance| *instance == 2));
+    assert_eq!(playing, [1, 3]);
+    assert!(has_room(&mut playing, 3, |_| true));
+    assert!(playing.is_empty());
+}
+
+#[test]
+fn playlist_loops_after_the_intro_and_crossfades_between_tracks() {
+    // The intro plays until it stops, the loop starts right there
+    assert_eq!(next_track(None, 2, 10.0, 12.0), None);
+    assert_eq!(
+        next_track(None, 2, f64::INFINITY, 12.0),
+        Some(NextTrack::Start(0))
+    );
+    // Tracks fade into the next one shortly before they end, the last one into the first
+    assert_eq!(next_track(Some(0), 2, 9.0, 12.0), None);
+    assert_eq!(
+        next_track(Some(0), 2, 10.5, 12.0),
+        Some(NextTrack::Crossfade {
+            track: 1,
+            fade_secs: 2.0
+        })
+    );
+    assert_eq!(
+        next_track(Some(1), 2, 11.0, 12.0),
+        Some(NextTrack::Crossfade {
+            track: 0,
+            fade_secs: 2.0
+        })
+    );
+    // A single short track doesn't crossfade into itself over and over
+    assert_eq!(next_track(Some(0), 1, 0.2, 1.0), None);
+    assert_eq!(
+        next_track(Some(0), 1, 0.6, 1.0),
+        Some(NextTrack::Crossfade {
+            track: 0,
+            fade_secs: 0.5
+        })
+    );
+    assert_eq!(next_track(Some(0), 0, f64::INFINITY, 12.0), None);
+}
+
+#[test]
+fn calm_and_combat_music_switch_on_bars() {
+    // 120 bpm in 4/4
+    let bar_secs = 2.0;
+    assert!(!bar_started(0.0, 1.9, bar_secs));
+    assert!(bar_started(1.9, 2.1, bar_secs));
+    assert!(!bar_started(2.1, 3.9, bar_secs));
+    assert!(bar_started(3.9, 4.0, bar_secs));
+    // Frames can be long enough to skip a bar
+    assert!(bar_started(1.0, 5.0, bar_secs));
+}
+
+#[test]
+fn replays_leave_pausing_to_the_viewer() {
+    let mut app = playing_app();
+    let pause_state = |app: &App| *app.world().resource::<State<PauseState>>().get();
+    // Pauses and goes on, all of it recorded
+    step(&mut app, 10);
+    keys(&mut app).press(KeyCode::Escape);
+    step(&mut app, 2);
+    keys(&mut app).release(KeyCode::Escape);
+    step(&mut app, 5);
+    assert_eq!(pause_state(&app), PauseState::Paused);
+    keys(&mut app).press(KeyCode::Escape);
+    step(&mut app, 2);
+    keys(&mut app).release(KeyCode::Escape);
+    step(&mut app, 20);
+    assert_eq!(pause_state(&app), PauseState::Running);
+    app.world_mut()
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::Menu);
+    step(&mut app, 2);
+
+    app.world_mut()
+        .spawn((WatchLastRun, Button, Interaction::Pressed));
+    step(&mut app, 3);
+    assert_eq!(
+        *app.world().resource::<State<GameState>>().get(),
+        GameState::Playing
+    );
+    for _ in 0..30 {
+        app.update();
+        assert_eq!(pause_state(&app), PauseState::Running);
+    }
+
+    keys(&mut app).press(KeyCode::Escape);
+    step(&mut app, 2);
+    keys(&mut app).release(KeyCode::Escape);
+    step(&mut app, 2);
+    assert_eq!(pause_state(&app), PauseState::Paused);
+    keys(&mut app).press(KeyCode::Escape);
+    step(&mut app, 2);
+    keys(&mut app).release(KeyCode::Escape);
+    step(&mut app, 2);
+    assert_eq!(pause_state(&app), PauseState::Running);
+}
@@ -11,6 +11,7 @@
 // Functional limitations:
 //   * When the 'atlas' feature is enabled tilesets using a collection of images will be skipped.
 //   * Only finite tile layers are loaded. Infinite tile layers and object layers will be skipped.
+//     The pickups in object layers are spawned by `crate::tilemap`.
 
 use std::io::{Cursor, ErrorKind};
 use std::path::Path;
@@ -1,7 +1,9 @@
 use bevy::prelude::*;
-use bevy_ecs_tilemap::prelude::TilemapRenderSettings;
+use bevy_ecs_tilemap::prelude::*;
 
+use crate::camera::MapBounds;
 use crate::loading::TileMapAssets;
+use crate::pickups::{spawn_pickup, Pickup};
 use crate::tiled::*;
 use crate::GameState;
 
@@ -10,10 +12,18 @@ pub struct TilemapPlugin;
 impl Plugin for TilemapPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(bevy_ecs_tilemap::TilemapPlugin)
-            .add_systems(OnEnter(GameState::Playing), setup_map);
+            .add_systems(OnEnter(GameState::Playing), setup_map)
+            .add_systems(
+                Update,
+                spawn_map_contents.run_if(in_state(GameState::Playing)),
+            );
     }
 }
 
+/// The bounds and objects of the map were set up
+#[derive(Component)]
+struct ContentsSpawned;
+
 fn setup_map(mut commands: Commands, maps: Res<TileMapAssets>) {
     commands.spawn((
         TiledMapBundle {
@@ -27,3 +37,110 @@ fn setup_map(mut commands: Commands, maps: Res<TileMapAssets>) {
         StateScoped(GameState::Playing),
     ));
 }
+
+/// Sets the [`MapBounds`] and spawns the pickups once the map is loaded.
+/// Pickups are placed as objects of the class `pickup`. Their `pickup` property says which one,
+/// written like in the drop tables, e.g. `Heal(1)`.
+fn spawn_map_contents(
+    mut commands: Commands,
+    maps: Res<Assets<TiledMap>>,
+    map_query: Query<(Entity, &TiledMapHandle), Without<ContentsSpawned>>,
+) {
+    for (map_entity, map_handle) in map_query.iter() {
+        let Some(tiled_map) = maps.get(&map_handle.0) else {
+            continue;
+        };
+        commands.entity(map_entity).insert(ContentsSpawned);
+        let map = &tiled_map.map;
+        let map_size = TilemapSize {
+            x: map.width,
+            y: map.height,
+        };
+        let grid_size = TilemapGridSize {
+            x: map.tile_width as f32,
+            y: map.tile_height as f32,
+        };
+        // Object coordinates count tiles in their height along both axes of isometric maps
+        let (map_type, tile_size) = match map.orientation {
+            tiled::Orientation::Orthogonal => {
+                (TilemapType::Square, Vec2::new(grid_size.x, grid_size.y))
+            }
+            tiled::Orientation::Isometric => (
+                TilemapType::Isometric(IsoCoordSystem::Diamond),
+                Vec2::splat(grid_size.y),
+            ),
+            _ => {
+                warn!("Only orthogonal and isometric maps have bounds and pickups");
+                continue;
+            }
+        };
+        // Like the layers, see `process_loaded_maps`
+        let center = get_tilemap_center_transform(&map_size, &grid_size, &map_type, 0.0);
+        let world_position = |tile_pos: TilePos| -> Vec2 {
+            center
+                .transform_point(tile_pos.center_in_world(&grid_size, &map_type).extend(0.0))
+                .xy()
+        };
+
+        // The corner tiles are the outermost ones, in isometric maps too
+        let corners = [
+            TilePos { x: 0, y: 0 },
+            TilePos {
+                x: map_size.x - 1,
+                y: 0,
+            },
+            TilePos {
+                x: 0,
+                y: map_size.y - 1,
+            },
+            TilePos {
+                x: map_size.x - 1,
+                y: map_size.y - 1,
+            },
+        ]
+        .map(world_position);
+        let (min, max) = (
+            corners.into_iter().reduce(Vec2::min).expect("Four corners"),
+            corners.into_iter().reduce(Vec2::max).expect("Four corners"),
+        );
+        commands.insert_resource(MapBounds {
+            center: (min + max) / 2.0,
+            half_size: (max - min + Vec2::new(grid_size.x, grid_size.y)) / 2.0,
+            diamond: matches!(map_type, TilemapType::Isometric(_)),
+        });
+
+        for layer in map.layers() {
+            let tiled::LayerType::Objects(object_layer) = layer.layer_type() else {
+                continue;
+            };
+            for object in object_layer.objects() {
+                if object.user_type != "pickup" {
+                    continue;
+                }
+                let Some(tiled::PropertyValue::StringValue(value)) =
+                    object.properties.get("pickup")
+                else {
+                    warn!("Pickup object {} has no pickup property", object.id());
+                    continue;
+                };
+                let pickup = match ron::from_str::<Pickup>(value) {
+                    Ok(pickup) => pickup,
+                    Err(e) => {
+                        warn!("Pickup object {} is invalid: {e}", object.id());
+                        continue;
+                    }
+                };
+                let tile = (Vec2::new(object.x, object.y) / tile_size).as_uvec2();
+                // Tiled counts rows from the top
+                let Some(y) = map.height.checked_sub(tile.y + 1) else {
+                    continue;
+                };
+                let tile_pos = TilePos { x: tile.x, y };
+                if !tile_pos.within_map_bounds(&map_size) {
+                    continue;
+                }
+                spawn_pickup(&mut commands, pickup, world_position(tile_pos));
+            }
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowResized};
+
+use crate::actions::{GameControl, InputBindings, TouchMode, VirtualInput, VirtualInputSet};
+use crate::loading::Fonts;
+use crate::GameState;
+
+pub struct TouchControlsPlugin;
+
+/// This plugin shows an on-screen stick and buttons once a touch was detected.
+/// The stick appears wherever a touch starts on the left half of the screen. All touches are
+/// tracked separately, so it's possible to move and attack at the same time.
+impl Plugin for TouchControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TouchDetected>()
+            .init_resource::<TouchStick>()
+            .add_systems(
+                PreUpdate,
+                update_virtual_input
+                    .in_set(VirtualInputSet)
+                    .after(InputSystem)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnExit(GameState::Playing), release_touch_stick)
+            .add_systems(
+                Update,
+                (
+                    detect_touch,
+                    spawn_touch_controls.run_if(in_state(GameState::Playing)),
+                    layout_touch_controls,
+                    update_stick_display,
+                    update_button_display,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Set once the first touch happened, the controls stay hidden before
+#[derive(Resource, Default)]
+struct TouchDetected(bool);
+
+/// The touch currently controlling the stick: id and where it started
+#[derive(Resource, Default)]
+struct TouchStick(Option<(u64, Vec2)>);
+
+#[derive(Component)]
+struct TouchControlsRoot;
+
+#[derive(Component)]
+struct ButtonCluster;
+
+#[derive(Component)]
+struct PauseButton;
+
+#[derive(Component)]
+struct StickBase;
+
+#[derive(Component)]
+struct StickKnob;
+
+#[derive(Component)]
+struct VirtualButton(GameControl);
+
+/// Radius of the stick relative to the shorter side of the window
+const STICK_RADIUS: f32 = 0.12;
+const BUTTON_COLOR: Color = Color::linear_rgba(0.8, 0.8, 0.8, 0.25);
+const BUTTON_PRESSED_COLOR: Color = Color::linear_rgba(0.8, 0.8, 0.8, 0.5);
+
+fn detect_touch(touch_input: Res<Touches>, mut touch_detected: ResMut<TouchDetected>) {
+    if !touch_detected.0 && touch_input.any_just_pressed() {
+        touch_detected.0 = true;
+    }
+}
+
+fn stick_radius(window: &Window) -> f32 {
+    window.width().min(window.height()) * STICK_RADIUS
+}
+
+fn spawn_touch_controls(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    bindings: Res<InputBindings>,
+    touch_detected: Res<TouchDetected>,
+    root_query: Query<(), With<TouchControlsRoot>>,
+) {
+    if !touch_detected.0 || bindings.touch != TouchMode::VirtualControls || !root_query.is_empty() {
+        return;
+    }
+    let round_button = |size: f32, control: GameControl| {
+        (
+            Node {
+                width: Val::VMin(size),
+                height: Val::VMin(size),
+                margin: UiRect::all(Val::VMin(1.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BorderRadius::MAX,
+            BackgroundColor(BUTTON_COLOR),
+            VirtualButton(control),
+        )
+    };
+    let label = |text: &str| {
+        (
+            Text::new(text),
+            TextFont {
+                font: fonts.font.clone(),
+                font_size: 24.0,
+                ..default()
+            },
+        )
+    };
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            TouchControlsRoot,
+            StateScoped(GameState::Playing),
+        ))
+        .with_children(|root| {
+            root.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                },
+                BorderRadius::MAX,
+                BackgroundColor(BUTTON_COLOR),
+                Visibility::Hidden,
+                StickBase,
+            ))
+            .with_child((
+                Node {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                },
+                BorderRadius::MAX,
+                BackgroundColor(BUTTON_PRESSED_COLOR),
+                StickKnob,
+            ));
+            root.spawn(round_button(8.0, GameControl::Pause))
+                .insert(Node {
+                    position_type: PositionType::Absolute,
+                    width: Val::VMin(8.0),
+                    height: Val::VMin(8.0),
+                    right: Val::Px(0.0),
+                    top: Val::Px(0.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                })
+                .insert(PauseButton)
+                .with_child(label("II"));
+            root.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(0.0),
+                    bottom: Val::Px(0.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::End,
+                    ..default()
+                },
+                ButtonCluster,
+            ))
+            .with_children(|cluster| {
+                cluster
+                    .spawn(Node {
+                        align_items: AlignItems::End,
+                        ..default()
+                    })
+                    .with_children(|top_row| {
+                        top_row
+                            .spawn(round_button(11.0, GameControl::SwitchForm))
+                            .with_child(label("Form"));
+                        top_row
+                            .spawn(round_button(11.0, GameControl::Dash))
+                            .with_child(label("Dash"));
+                    });
+                cluster
+                    .spawn(round_button(18.0, GameControl::Attack))
+                    .with_child(label("Attack"));
+            });
+        });
+}
+
+/// Keeps the controls out of notches and the home indicator: Those are on the short sides in
+/// landscape and the long sides in portrait. Bevy doesn't expose the actual safe area insets.
+fn layout_touch_controls(
+    mut resize_events: EventReader<WindowResized>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    added_query: Query<(), Added<TouchControlsRoot>>,
+    mut cluster_query: Query<&mut Node, (With<ButtonCluster>, Without<PauseButton>)>,
+    mut pause_query: Query<&mut Node, (With<PauseButton>, Without<ButtonCluster>)>,
+) {
+    let resized = resize_events.read().count() > 0;
+    if !resized && added_query.is_empty() {
+        return;
+    }
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+    let landscape = window.width() >= window.height();
+    let (side, top, bottom) = if landscape {
+        (Val::Vw(6.0), Val::Vh(3.0), Val::Vh(8.0))
+    } else {
+        (Val::Vw(3.0), Val::Vh(5.0), Val::Vh(6.0))
+    };
+    for mut node in cluster_query.iter_mut() {
+        node.right = side;
+        node.bottom = bottom;
+    }
+    for mut node in pause_query.iter_mut() {
+        node.right = side;
+        node.top = top;
+    }
+}
+
+fn update_virtual_input(
+    touch_input: Res<Touches>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    button_query: Query<(&VirtualButton, &ComputedNode, &GlobalTransform)>,
+    mut touch_stick: ResMut<TouchStick>,
+    mut virtual_input: ResMut<VirtualInput>,
+) {
+    virtual_input.movement = Vec2::ZERO;
+    virtual_input.pressed.clear();
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+
+    let on_button = |position: Vec2| {
+        button_query.iter().find_map(|(button, node, transform)| {
+            // UI nodes are laid out in physical pixels, touches are logical
+            let center = transform.translation().truncate() * node.inverse_scale_factor();
+            let half_size = node.size() * node.inverse_scale_factor() / 2.0;
+            Rect::from_center_half_size(center, half_size)
+                .contains(position)
+                .then_some(button.0)
+        })
+    };
+
+    if let Some((id, _)) = touch_stick.0 {
+        if touch_input.get_pressed(id).is_none() {
+            touch_stick.0 = None;
+        }
+    }
+    for touch in touch_input.iter_just_pressed() {
+        if touch_stick.0.is_none()
+            && touch.start_position().x < window.width() / 2.0
+            && on_button(touch.start_position()).is_none()
+        {
+            touch_stick.0 = Some((touch.id(), touch.start_position()));
+        }
+    }
+
+    for touch in touch_input.iter() {
+        match touch_stick.0 {
+            Some((id, origin)) if id == touch.id() => {
+                let offset = (touch.position() - origin) / stick_radius(window);
+                // Screen coordinates point down
+                virtual_input.movement = Vec2::new(offset.x, -offset.y).clamp_length_max(1.0);
+            }
+            _ => {
+                if let Some(control) = on_button(touch.position()) {
+                    virtual_input.pressed.insert(control);
+                }
+            }
+        }
+    }
+}
+
+/// Fingers still down when the run ends don't keep holding controls in the menus
+fn release_touch_stick(
+    mut touch_stick: ResMut<TouchStick>,
+    mut virtual_input: ResMut<VirtualInput>,
+) {
+    touch_stick.0 = None;
+    *virtual_input = default();
+}
+
+fn update_stick_display(
+    touch_stick: Res<TouchStick>,
+    touch_input: Res<Touches>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut base_query: Query<(&mut Node, &mut Visibility), (With<StickBase>, Without<StickKnob>)>,
+    mut knob_query: Query<&mut Node, (With<StickKnob>, Without<StickBase>)>,
+) {
+    let (Ok((mut base_node, mut base_visibility)), Ok(mut knob_node), Ok(window)) = (
+        base_query.get_single_mut(),
+        knob_query.get_single_mut(),
+        window_query.get_single(),
+    ) else {
+        return;
+    };
+    let Some((id, origin)) = touch_stick.0 else {
+        *base_visibility = Visibility::Hidden;
+        return;
+    };
+    let position = touch_input.get_pressed(id).map_or(origin, |t| t.position());
+    let radius = stick_radius(window);
+    let knob_radius = radius * 0.4;
+    let offset = (position - origin).clamp_length_max(radius);
+
+    *base_visibility = Visibility::Inherited;
+    base_node.left = Val::Px(origin.x - radius);
+    base_node.top = Val::Px(origin.y - radius);
+    base_node.width = Val::Px(radius * 2.0);
+    base_node.height = Val::Px(radius * 2.0);
+    knob_node.left = Val::Px(radius + offset.x - knob_radius);
+    knob_node.top = Val::Px(radius + offset.y - knob_radius);
+    knob_node.width = Val::Px(knob_radius * 2.0);
+    knob_node.height = Val::Px(knob_radius * 2.0);
+}
+
+fn update_button_display(
+    virtual_input: Res<VirtualInput>,
+    mut button_query: Query<(&VirtualButton, &mut BackgroundColor)>,
+) {
+    for (button, mut color) in button_query.iter_mut() {
+        color.0 = if virtual_input.pressed.contains(&button.0) {
+            BUTTON_PRESSED_COLOR
+        } else {
+            BUTTON_COLOR
+        };
+    }
+}
@@ -3,7 +3,8 @@ use bevy_aseprite_ultra::prelude::*;
 
 use crate::actions::{Actions, Health};
 use crate::loading::{Fonts, TextureAssets};
-use crate::player::Player;
+use crate::player::{Player, PLAYER_COLORS};
+use crate::progression::Experience;
 use crate::GameState;
 
 pub struct UiPlugin;
@@ -13,7 +14,14 @@ impl Plugin for UiPlugin {
         app.add_systems(Update, update_cooldown_displays)
             .add_systems(
                 Update,
-                (update_health_display, update_score_display).run_if(in_state(GameState::Playing)),
+                (
+                    spawn_player_huds,
+                    update_health_display,
+                    update_score_display,
+                    update_level_display,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
             );
     }
 }
@@ -21,11 +29,17 @@ impl Plugin for UiPlugin {
 #[derive(Component)]
 pub struct CooldownDisplay(pub Entity);
 
+/// Shows the score of the player entity
 #[derive(Component)]
-pub struct ScoreDisplay;
+pub struct ScoreDisplay(pub Entity);
 
+/// Shows the level and XP of the player entity
 #[derive(Component)]
-pub struct HealthDisplay;
+pub struct LevelDisplay(pub Entity);
+
+/// Shows the health of the player entity
+#[derive(Component)]
+pub struct HealthDisplay(pub Entity);
 
 fn update_cooldown_displays(
     mut display_query: Query<(Entity, &mut Text2d, &mut Visibility, &CooldownDisplay)>,
@@ -52,101 +66,142 @@ fn update_cooldown_displays(
     }
 }
 
-fn update_health_display(
+/// Puts every player's HUD in their own corner
+fn spawn_player_huds(
     mut commands: Commands,
-    textures: Res<TextureAssets>,
-    player_query: Query<&Health, (Changed<Health>, With<Player>)>,
-    health_query: Query<Entity, With<HealthDisplay>>,
+    fonts: Res<Fonts>,
+    player_query: Query<(Entity, &Player), Added<Player>>,
 ) {
-    let health_entity = health_query.get_single();
-    let Ok(player_health) = player_query.get_single() else {
-        if let Ok(health_entity) = health_entity {
-            commands.entity(health_entity).despawn_recursive();
-        }
-        return;
-    };
-    let health_entity = if let Ok(health_entity) = health_entity {
-        commands.entity(health_entity).despawn_descendants();
-        health_entity
-    } else {
+    for (player_entity, player) in player_query.iter() {
+        let (left, right) = if player.id % 2 == 0 {
+            (Val::Px(100.0), Val::Auto)
+        } else {
+            (Val::Auto, Val::Px(100.0))
+        };
+        let (top, bottom) = if player.id < 2 {
+            (Val::Px(20.0), Val::Auto)
+        } else {
+            (Val::Auto, Val::Px(20.0))
+        };
         commands
             .spawn((
                 Node {
                     position_type: PositionType::Absolute,
-                    left: Val::Px(100.0),
-                    top: Val::Px(20.0),
-                    width: Val::Px(128.0),
-                    height: Val::Px(64.0),
+                    left,
+                    right,
+                    top,
+                    bottom,
+                    flex_direction: FlexDirection::Column,
+                    align_items: if player.id % 2 == 0 {
+                        AlignItems::Start
+                    } else {
+                        AlignItems::End
+                    },
                     ..default()
                 },
                 StateScoped(GameState::Playing),
-                HealthDisplay,
             ))
-            .id()
-    };
-    commands.entity(health_entity).with_children(|parent| {
-        for i in 0..player_health.max_health {
-            if i < player_health.health {
-                parent.spawn((
-                    AseUiAnimation {
-                        aseprite: textures.player_life.clone(),
-                        animation: Animation::tag("beating"),
-                    },
+            .with_children(|hud| {
+                hud.spawn((
                     Node {
-                        width: Val::Px(38.0),
                         height: Val::Px(38.0),
                         ..default()
                     },
+                    HealthDisplay(player_entity),
                 ));
-            } else {
-                parent.spawn((
-                    AseUiAnimation {
-                        aseprite: textures.player_life.clone(),
-                        animation: Animation::tag("depleted"),
+                hud.spawn((
+                    Text::new(player.score.to_string()),
+                    TextFont {
+                        font: fonts.font.clone(),
+                        font_size: 48.0,
+                        ..default()
                     },
-                    Node {
-                        width: Val::Px(38.0),
-                        height: Val::Px(38.0),
+                    TextColor(PLAYER_COLORS[player.id]),
+                    ScoreDisplay(player_entity),
+                ));
+                hud.spawn((
+                    Text::default(),
+                    TextFont {
+                        font: fonts.font.clone(),
+                        font_size: 24.0,
                         ..default()
                     },
+                    TextColor(PLAYER_COLORS[player.id]),
+                    LevelDisplay(player_entity),
                 ));
-            }
+            });
+    }
+}
+
+fn update_health_display(
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    player_query: Query<Ref<Health>, With<Player>>,
+    health_query: Query<(Entity, Ref<HealthDisplay>)>,
+) {
+    for (health_entity, display) in health_query.iter() {
+        let Ok(player_health) = player_query.get(display.0) else {
+            // The player died
+            commands.entity(health_entity).despawn_descendants();
+            continue;
+        };
+        if !player_health.is_changed() && !display.is_added() {
+            continue;
         }
-    });
+        commands
+            .entity(health_entity)
+            .despawn_descendants()
+            .with_children(|parent| {
+                for i in 0..player_health.max_health {
+                    let animation = if i < player_health.health {
+                        // The last heart races along with the heartbeat, see `crate::audio`
+                        let speed = if player_health.health <= 1 { 2.0 } else { 1.0 };
+                        Animation::tag("beating").with_speed(speed)
+                    } else {
+                        Animation::tag("depleted")
+                    };
+                    parent.spawn((
+                        AseUiAnimation {
+                            aseprite: textures.player_life.clone(),
+                            animation,
+                        },
+                        Node {
+                            width: Val::Px(38.0),
+                            height: Val::Px(38.0),
+                            ..default()
+                        },
+                    ));
+                }
+            });
+    }
 }
 
 fn update_score_display(
-    mut commands: Commands,
-    fonts: Res<Fonts>,
     player_query: Query<&Player, Changed<Player>>,
-    score_query: Query<Entity, With<ScoreDisplay>>,
+    mut score_query: Query<(&ScoreDisplay, &mut Text)>,
 ) {
-    let Ok(player) = player_query.get_single() else {
-        return;
-    };
-    let score_entity = score_query.get_single();
-    let mut ec = if let Ok(score_entity) = score_entity {
-        commands.entity(score_entity)
-    } else {
-        commands.spawn((
-            Node {
-                position_type: PositionType::Absolute,
-                top: Val::Px(20.0),
-                left: Val::Percent(40.0),
-                width: Val::Percent(100.0),
-                height: Val::Px(64.0),
-                ..default()
-            },
-            StateScoped(GameState::Playing),
-            ScoreDisplay,
-        ))
-    };
-    ec.insert((
-        Text::new(player.score.to_string()),
-        TextFont {
-            font: fonts.font.clone(),
-            font_size: 48.0,
-            ..default()
-        },
-    ));
+    for (ScoreDisplay(player_entity), mut text) in score_query.iter_mut() {
+        if let Ok(player) = player_query.get(*player_entity) {
+            text.0 = player.score.to_string();
+        }
+    }
+}
+
+fn update_level_display(
+    player_query: Query<Ref<Experience>>,
+    mut level_query: Query<(Ref<LevelDisplay>, &mut Text)>,
+) {
+    for (display, mut text) in level_query.iter_mut() {
+        let Ok(experience) = player_query.get(display.0) else {
+            continue;
+        };
+        if experience.is_changed() || display.is_added() {
+            text.0 = format!(
+                "Lv {} - {}/{} XP",
+                experience.level,
+                experience.xp,
+                experience.needed()
+            );
+        }
+    }
 }